@@ -0,0 +1,152 @@
+use std::ops::Index;
+
+/// The locked blocks. Each row is kept twice: as a bitmask of its filled
+/// columns, which collision checks and line clears work on, and as the
+/// color numbers the blocks are drawn with.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Board {
+    width: usize,
+    // Bit x of a row is column x.
+    rows: Vec<u16>,
+    // Row after row, `width` colors each, 0 for an empty cell.
+    colors: Vec<u8>,
+}
+
+impl Board {
+    pub fn new(width: usize, height: usize) -> Board {
+        assert!(width <= 16, "boards are at most 16 columns wide");
+        Board {
+            width,
+            rows: vec![0; height],
+            colors: vec![0; width * height],
+        }
+    }
+
+    /// A board with the colors of `rows`, top row first.
+    pub fn from_rows(rows: Vec<Vec<u8>>) -> Board {
+        let mut board = Board::new(rows[0].len(), rows.len());
+        for (y, row) in rows.iter().enumerate() {
+            for (x, &case) in row.iter().enumerate() {
+                board.set(x, y, case);
+            }
+        }
+        board
+    }
+
+    pub fn to_rows(&self) -> Vec<Vec<u8>> {
+        self.lines().map(|row| row.to_vec()).collect()
+    }
+
+    pub fn height(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// The colors of each row, top row first.
+    pub fn lines(&self) -> impl Iterator<Item = &[u8]> {
+        self.colors.chunks(self.width)
+    }
+
+    fn full_row(&self) -> u16 {
+        ((1u32 << self.width) - 1) as u16
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, case: u8) {
+        assert!(x < self.width);
+        self.colors[y * self.width + x] = case;
+        if case == 0 {
+            self.rows[y] &= !(1 << x);
+        } else {
+            self.rows[y] |= 1 << x;
+        }
+    }
+
+    pub fn is_row_full(&self, y: usize) -> bool {
+        self.rows[y] == self.full_row()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.iter().all(|&row| row == 0)
+    }
+
+    /// How many rows up from the floor the highest block is.
+    pub fn stack_height(&self) -> usize {
+        self.rows.iter().position(|&row| row != 0).map_or(0, |top| self.height() - top)
+    }
+
+    /// Whether a piece whose rows have the filled columns in `mask` fits
+    /// with its top left corner at `x`, `y`, inside the board and clear of
+    /// every block.
+    pub fn fits(&self, mask: &[u16], x: isize, y: usize) -> bool {
+        for (row, &bits) in mask.iter().enumerate() {
+            if bits == 0 {
+                continue;
+            }
+            // Every cell would be past one side, which also keeps the
+            // shifts below in range.
+            if x >= self.width as isize || x <= -16 {
+                return false;
+            }
+            let Some(&board_row) = self.rows.get(y + row) else {
+                return false;
+            };
+            let shifted = if x >= 0 {
+                (bits as u32) << x
+            } else {
+                let shift = (-x) as u32;
+                if bits as u32 & ((1 << shift) - 1) != 0 {
+                    return false;
+                }
+                (bits >> shift) as u32
+            };
+            if shifted & !(self.full_row() as u32) != 0 || shifted & board_row as u32 != 0 {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Adds `row` at the bottom and pushes everything up. Returns whether
+    /// the row pushed out of the top had blocks in it.
+    pub fn push_row(&mut self, row: &[u8]) -> bool {
+        let overflow = self.rows.remove(0) != 0;
+        self.colors.drain(..self.width);
+        self.rows.push(0);
+        self.colors.extend(vec![0; self.width]);
+        let bottom = self.height() - 1;
+        for (x, &case) in row.iter().enumerate() {
+            self.set(x, bottom, case);
+        }
+        overflow
+    }
+
+    /// Removes the full rows, lets the rest fall and returns how many were
+    /// removed.
+    pub fn clear_lines(&mut self) -> u32 {
+        let full_row = self.full_row();
+        let mut lines = 0;
+        let mut y = self.height();
+        while y > 0 {
+            y -= 1;
+            if self.rows[y] == full_row {
+                self.rows.remove(y);
+                self.colors.drain(y * self.width..(y + 1) * self.width);
+                lines += 1;
+            }
+        }
+        for _ in 0..lines {
+            self.rows.insert(0, 0);
+        }
+        self.colors.splice(..0, vec![0; lines as usize * self.width]);
+        lines
+    }
+}
+
+impl Index<usize> for Board {
+    type Output = [u8];
+
+    /// The colors of row `y`.
+    fn index(&self, y: usize) -> &[u8] {
+        &self.colors[y * self.width..(y + 1) * self.width]
+    }
+}
@@ -0,0 +1,219 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::mode::{self, GameMode, Variants};
+use crate::tetris::{self, LEVEL_TIMES};
+
+pub const USAGE: &str = "\
+Usage: tetris [OPTIONS]
+       tetris verify <REPLAY>
+
+Commands:
+  verify <REPLAY>          Play a replay back without a window, print the
+                           result and exit with 1 if it does not match the
+                           recorded one
+
+Options:
+  -l, --level <N>          Starting level, 1 to 10 [default: 1]
+  -m, --mode <MODE>        Game mode: marathon-150, marathon-200, endless,
+                           sprint, ultra, ultra-2, dig, survival, master, pc
+                           [default: marathon-150]
+      --lines <N>          Line goal for marathon, sprint and dig, 1 to 999
+      --messiness <PCT>    Chance that a garbage hole moves in dig and survival,
+                           0 to 100 [default: 30 for dig, 50 for survival]
+      --invisible          Hide locked blocks until lines clear or the game ends
+      --big                Make every piece cell cover 2x2 board cells, on an
+                           even --width of at least 8
+      --pieces <SET>       Deal the pieces of a set instead of the tetrominoes:
+                           pentominoes, small or a piece set file
+      --seed <N>           Seed for the piece randomizer [default: random]
+      --width <N>          Board width in cells, 4 to 16 [default: 10]
+      --height <N>         Board height in cells, 4 to 40 [default: 16]
+      --scale <FACTOR>     Window scale factor, 0.25 to 4 [default: 1]
+      --fullscreen         Start in fullscreen
+      --score-file <PATH>  High score file [default: $XDG_DATA_HOME/tetris/scores.txt]
+      --config <PATH>      Configuration file [default: $XDG_CONFIG_HOME/tetris/config.toml]
+      --puzzle <PATH>      Play the puzzle in a board file
+      --replay <PATH>      Play back a recorded replay
+      --headless           Verify the --replay without a window, like verify
+  -h, --help               Print this help
+";
+
+pub struct Options {
+    pub level: u32,
+    pub mode: GameMode,
+    pub variants: Variants,
+    // The built-in set or file `--pieces` named, loaded once the game
+    // starts.
+    pub pieces: Option<String>,
+    pub seed: Option<u64>,
+    pub board_width: usize,
+    pub board_height: usize,
+    pub scale: f32,
+    pub fullscreen: bool,
+    pub score_file: Option<PathBuf>,
+    pub config: Option<PathBuf>,
+    pub puzzle: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub headless: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            level: 1,
+            mode: GameMode::Marathon { lines: Some(mode::MARATHON_LINES[0]) },
+            variants: Variants::default(),
+            pieces: None,
+            seed: None,
+            board_width: tetris::DEFAULT_WIDTH,
+            board_height: tetris::DEFAULT_HEIGHT,
+            scale: 1.0,
+            fullscreen: false,
+            score_file: None,
+            config: None,
+            puzzle: None,
+            replay: None,
+            headless: false,
+        }
+    }
+}
+
+pub enum Command {
+    Play(Options),
+    Verify(PathBuf),
+    Help,
+}
+
+#[derive(Debug)]
+pub struct CliError(String);
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+fn value<I: Iterator<Item = String>>(args: &mut I, flag: &str) -> Result<String, CliError> {
+    args.next().ok_or_else(|| CliError(format!("{} needs a value", flag)))
+}
+
+fn number<T: FromStr>(value: &str, flag: &str) -> Result<T, CliError> {
+    value.parse().map_err(|_| CliError(format!("invalid value '{}' for {}", value, flag)))
+}
+
+fn in_range<T: PartialOrd + fmt::Display>(value: T, min: T, max: T, flag: &str) -> Result<T, CliError> {
+    if value < min || value > max {
+        Err(CliError(format!("{} must be between {} and {}", flag, min, max)))
+    } else {
+        Ok(value)
+    }
+}
+
+/// Parses the arguments after the program name.
+pub fn parse<I: Iterator<Item = String>>(args: I) -> Result<Command, CliError> {
+    let mut args = args.peekable();
+    if args.peek().map(String::as_str) == Some("verify") {
+        args.next();
+        return match (args.next(), args.next()) {
+            (Some(ref help), None) if help == "-h" || help == "--help" => Ok(Command::Help),
+            (Some(path), None) => Ok(Command::Verify(PathBuf::from(path))),
+            (None, _) => Err(CliError("verify needs a replay file".to_string())),
+            (Some(_), Some(extra)) => Err(CliError(format!("unexpected argument '{}'", extra))),
+        };
+    }
+    let mut options = Options::default();
+    let mut line_goal = None;
+    let mut messiness = None;
+
+    while let Some(arg) = args.next() {
+        // Accept both `--flag value` and `--flag=value`.
+        let (flag, inline) = match arg.find('=') {
+            Some(index) if arg.starts_with("--") => (arg[..index].to_string(), Some(arg[index + 1..].to_string())),
+            _ => (arg.clone(), None),
+        };
+        let take = |args: &mut Peekable<I>| match inline.clone() {
+            Some(inline) => Ok(inline),
+            None => value(args, &flag),
+        };
+
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-l" | "--level" => {
+                let level = number(&take(&mut args)?, &flag)?;
+                options.level = in_range(level, 1, LEVEL_TIMES.len() as u32, &flag)?;
+            },
+            "-m" | "--mode" => {
+                let mode = take(&mut args)?;
+                options.mode = GameMode::from_name(&mode).ok_or_else(|| {
+                    CliError(format!("unknown mode '{}', expected one of: {}", mode, GameMode::NAMES.join(", ")))
+                })?;
+            },
+            "--lines" => {
+                let lines = number(&take(&mut args)?, &flag)?;
+                line_goal = Some(in_range(lines, 1, mode::MAX_LINE_GOAL, &flag)?);
+            },
+            "--messiness" => {
+                let percent = number(&take(&mut args)?, &flag)?;
+                messiness = Some(in_range(percent, 0, mode::MAX_MESSINESS, &flag)?);
+            },
+            "--invisible" => options.variants.invisible = true,
+            "--big" => options.variants.big = true,
+            "--pieces" => options.pieces = Some(take(&mut args)?),
+            "--seed" => options.seed = Some(number(&take(&mut args)?, &flag)?),
+            "--width" => {
+                let width = number(&take(&mut args)?, &flag)?;
+                options.board_width = in_range(width, tetris::MIN_WIDTH, tetris::MAX_WIDTH, &flag)?;
+            },
+            "--height" => {
+                let height = number(&take(&mut args)?, &flag)?;
+                options.board_height = in_range(height, tetris::MIN_HEIGHT, tetris::MAX_HEIGHT, &flag)?;
+            },
+            "--scale" => {
+                let scale = number(&take(&mut args)?, &flag)?;
+                options.scale = in_range(scale, 0.25, 4.0, &flag)?;
+            },
+            "--fullscreen" => options.fullscreen = true,
+            "--score-file" => options.score_file = Some(PathBuf::from(take(&mut args)?)),
+            "--config" => options.config = Some(PathBuf::from(take(&mut args)?)),
+            "--puzzle" => options.puzzle = Some(PathBuf::from(take(&mut args)?)),
+            "--replay" => options.replay = Some(PathBuf::from(take(&mut args)?)),
+            "--headless" => options.headless = true,
+            _ => return Err(CliError(format!("unknown option '{}'", arg))),
+        }
+    }
+
+    if let Some(goal) = line_goal {
+        options.mode = options.mode.with_line_goal(goal)
+            .ok_or_else(|| CliError("--lines only applies to marathon, sprint and dig".to_string()))?;
+    }
+    if let Some(messiness) = messiness {
+        options.mode = options.mode.with_messiness(messiness)
+            .ok_or_else(|| CliError("--messiness only applies to dig and survival".to_string()))?;
+    }
+    if options.variants.big {
+        // Prepared boards are laid out in single cells.
+        if options.puzzle.is_some() || options.mode == GameMode::PerfectClear {
+            return Err(CliError("--big does not apply to puzzles and pc training".to_string()));
+        }
+        if options.board_width % 2 != 0 {
+            return Err(CliError("--big needs an even --width".to_string()));
+        }
+        if options.board_width < options.variants.min_width() {
+            return Err(CliError(format!("--big needs a --width of at least {}", options.variants.min_width())));
+        }
+    }
+    if options.pieces.is_some() && (options.puzzle.is_some() || options.mode == GameMode::PerfectClear) {
+        // Both are built around the seven tetrominoes.
+        return Err(CliError("--pieces does not apply to puzzles and pc training".to_string()));
+    }
+    if options.headless {
+        return match options.replay {
+            Some(path) => Ok(Command::Verify(path)),
+            None => Err(CliError("--headless needs --replay".to_string())),
+        };
+    }
+    Ok(Command::Play(options))
+}
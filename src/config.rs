@@ -0,0 +1,329 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use toml_edit::{Array, DocumentMut, Item, Table, TableLike, Value};
+
+use crate::input::Action;
+use crate::storage;
+use crate::tetris::{ScoringProfile, MAX_PREVIEW};
+
+const CONFIG_FILE_NAME: &str = "config.toml";
+pub const MAX_DAS: u32 = 1000;
+pub const MAX_ARR: u32 = 500;
+pub const MAX_LOCK_DELAY: u32 = 5000;
+pub const MAX_SOFT_DROP: u32 = 1000;
+
+/// Movement timings, all in milliseconds.
+#[derive(Clone)]
+pub struct Handling {
+    // Delayed auto shift: how long left/right is held before it repeats.
+    pub das: u32,
+    // Auto repeat rate: time between repeated moves, 0 moves to the wall.
+    pub arr: u32,
+    pub lock_delay: u32,
+    // Time per cell while soft drop is held, 0 drops to the floor.
+    pub soft_drop: u32,
+}
+
+#[derive(Clone)]
+pub struct Visuals {
+    pub cell_size: u32,
+    pub ghost_piece: bool,
+    pub fullscreen: bool,
+}
+
+/// SDL key names for each binding, e.g. "Left", "Space" or "Left Shift".
+#[derive(Clone)]
+pub struct KeyBindings {
+    pub actions: [Vec<String>; 7],
+    pub settings: Vec<String>,
+    pub quit: Vec<String>,
+}
+
+impl KeyBindings {
+    pub fn get(&self, action: Action) -> &[String] {
+        &self.actions[action as usize]
+    }
+}
+
+#[derive(Clone)]
+pub struct Config {
+    pub handling: Handling,
+    pub visuals: Visuals,
+    pub keys: KeyBindings,
+    pub preview_count: usize,
+    pub scoring: ScoringProfile,
+}
+
+fn keys(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            handling: Handling {
+                das: 167,
+                arr: 33,
+                lock_delay: 500,
+                soft_drop: 50,
+            },
+            visuals: Visuals {
+                cell_size: 40,
+                ghost_piece: true,
+                fullscreen: false,
+            },
+            keys: KeyBindings {
+                actions: [
+                    keys(&["Left"]),
+                    keys(&["Right"]),
+                    keys(&["Down"]),
+                    keys(&["Space"]),
+                    keys(&["Up", "X"]),
+                    keys(&["Z"]),
+                    keys(&["C", "Left Shift"]),
+                ],
+                settings: keys(&["F1"]),
+                quit: keys(&["Escape"]),
+            },
+            preview_count: 3,
+            scoring: ScoringProfile::Classic,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(String),
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io(ref e) => write!(f, "{}", e),
+            ConfigError::Parse(ref e) => write!(f, "{}", e.trim_end()),
+            ConfigError::Invalid(ref errors) => {
+                write!(f, "invalid settings:")?;
+                for error in errors {
+                    write!(f, "\n  {}", error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/tetris/config.toml` unless a path was given.
+pub fn config_file(from_args: Option<PathBuf>) -> PathBuf {
+    from_args.unwrap_or_else(|| storage::config_dir().join(CONFIG_FILE_NAME))
+}
+
+// Collects every problem in the file so they can be fixed in one go.
+struct Validator {
+    errors: Vec<String>,
+}
+
+impl Validator {
+    fn section<'a>(&mut self, root: &'a dyn TableLike, name: &str) -> Option<&'a dyn TableLike> {
+        let item = root.get(name)?;
+        let table = item.as_table_like();
+        if table.is_none() {
+            self.errors.push(format!("{}: expected a table", name));
+        }
+        table
+    }
+
+    fn unknown_keys(&mut self, table: &dyn TableLike, prefix: &str, known: &[&str]) {
+        for (key, _) in table.iter() {
+            if !known.contains(&key) {
+                self.errors.push(format!("{}{}: unknown setting", prefix, key));
+            }
+        }
+    }
+
+    fn number(&mut self, table: &dyn TableLike, prefix: &str, key: &str, min: u32, max: u32, target: &mut u32) {
+        let Some(item) = table.get(key) else {
+            return;
+        };
+        match item.as_integer() {
+            Some(n) if n >= min as i64 && n <= max as i64 => *target = n as u32,
+            _ => self.errors.push(format!(
+                "{}{}: expected a whole number between {} and {}, found {}",
+                prefix, key, min, max, item.to_string().trim()
+            )),
+        }
+    }
+
+    fn boolean(&mut self, table: &dyn TableLike, prefix: &str, key: &str, target: &mut bool) {
+        let Some(item) = table.get(key) else {
+            return;
+        };
+        match item.as_bool() {
+            Some(b) => *target = b,
+            None => self.errors.push(format!(
+                "{}{}: expected true or false, found {}", prefix, key, item.to_string().trim()
+            )),
+        }
+    }
+
+    fn key_names(&mut self, table: &dyn TableLike, key: &str, target: &mut Vec<String>) {
+        let Some(item) = table.get(key) else {
+            return;
+        };
+        let names = item.as_array().and_then(|array| {
+            array.iter().map(|name| name.as_str().map(|name| name.to_string())).collect::<Option<Vec<_>>>()
+        });
+        match names {
+            Some(names) => *target = names,
+            None => self.errors.push(format!(
+                "keys.{}: expected a list of key names like [\"Left\", \"A\"], found {}", key, item.to_string().trim()
+            )),
+        }
+    }
+}
+
+// Replaces a value but keeps the comments and spacing around it.
+fn set<V: Into<Value>>(item: &mut Item, new: V) {
+    let mut new = new.into();
+    if let Some(old) = item.as_value() {
+        *new.decor_mut() = old.decor().clone();
+    }
+    *item = Item::Value(new);
+}
+
+fn section<'a>(doc: &'a mut DocumentMut, name: &str) -> &'a mut Item {
+    if !doc.contains_key(name) {
+        let mut new = Table::new();
+        new.decor_mut().set_prefix("\n");
+        doc[name] = Item::Table(new);
+    }
+    &mut doc[name]
+}
+
+impl Config {
+    /// Loads the config at `path`. A missing file gives the defaults.
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        match storage::read_file(path).map_err(ConfigError::Io)? {
+            Some(content) => Config::parse(&content),
+            None => Ok(Config::default()),
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<Config, ConfigError> {
+        let doc = content.parse::<DocumentMut>().map_err(|e| ConfigError::Parse(e.to_string()))?;
+        let root: &dyn TableLike = doc.as_table();
+        let mut config = Config::default();
+        let mut validator = Validator { errors: Vec::new() };
+
+        validator.unknown_keys(root, "", &["preview_count", "scoring", "handling", "visuals", "keys"]);
+
+        let mut preview_count = config.preview_count as u32;
+        validator.number(root, "", "preview_count", 0, MAX_PREVIEW as u32, &mut preview_count);
+        config.preview_count = preview_count as usize;
+
+        if let Some(item) = root.get("scoring") {
+            match item.as_str().and_then(ScoringProfile::from_name) {
+                Some(profile) => config.scoring = profile,
+                None => validator.errors.push(format!(
+                    "scoring: expected \"classic\" or \"guideline\", found {}", item.to_string().trim()
+                )),
+            }
+        }
+
+        if let Some(handling) = validator.section(root, "handling") {
+            validator.unknown_keys(handling, "handling.", &["das", "arr", "lock_delay", "soft_drop"]);
+            validator.number(handling, "handling.", "das", 0, MAX_DAS, &mut config.handling.das);
+            validator.number(handling, "handling.", "arr", 0, MAX_ARR, &mut config.handling.arr);
+            validator.number(handling, "handling.", "lock_delay", 0, MAX_LOCK_DELAY, &mut config.handling.lock_delay);
+            validator.number(handling, "handling.", "soft_drop", 0, MAX_SOFT_DROP, &mut config.handling.soft_drop);
+        }
+
+        if let Some(visuals) = validator.section(root, "visuals") {
+            validator.unknown_keys(visuals, "visuals.", &["cell_size", "ghost_piece", "fullscreen"]);
+            validator.number(visuals, "visuals.", "cell_size", 8, 128, &mut config.visuals.cell_size);
+            validator.boolean(visuals, "visuals.", "ghost_piece", &mut config.visuals.ghost_piece);
+            validator.boolean(visuals, "visuals.", "fullscreen", &mut config.visuals.fullscreen);
+        }
+
+        if let Some(keys) = validator.section(root, "keys") {
+            let mut known = Action::ALL.iter().map(|action| action.name()).collect::<Vec<_>>();
+            known.push("settings");
+            known.push("quit");
+            validator.unknown_keys(keys, "keys.", &known);
+
+            for &action in Action::ALL.iter() {
+                validator.key_names(keys, action.name(), &mut config.keys.actions[action as usize]);
+            }
+            validator.key_names(keys, "settings", &mut config.keys.settings);
+            validator.key_names(keys, "quit", &mut config.keys.quit);
+        }
+
+        if validator.errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError::Invalid(validator.errors))
+        }
+    }
+
+    /// Writes the settings back to `path`, keeping the comments and layout of
+    /// the existing file. A file that no longer parses is left alone.
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        let mut doc = match storage::read_file(path).map_err(ConfigError::Io)? {
+            Some(content) => content.parse::<DocumentMut>().map_err(|e| ConfigError::Parse(e.to_string()))?,
+            None => DocumentMut::new(),
+        };
+
+        set(&mut doc["preview_count"], self.preview_count as i64);
+        set(&mut doc["scoring"], self.scoring.name());
+
+        let handling = section(&mut doc, "handling");
+        set(&mut handling["das"], self.handling.das as i64);
+        set(&mut handling["arr"], self.handling.arr as i64);
+        set(&mut handling["lock_delay"], self.handling.lock_delay as i64);
+        set(&mut handling["soft_drop"], self.handling.soft_drop as i64);
+
+        let visuals = section(&mut doc, "visuals");
+        set(&mut visuals["cell_size"], self.visuals.cell_size as i64);
+        set(&mut visuals["ghost_piece"], self.visuals.ghost_piece);
+        set(&mut visuals["fullscreen"], self.visuals.fullscreen);
+
+        let keys = section(&mut doc, "keys");
+        let key_list = |names: &[String]| names.iter().map(|name| name.as_str()).collect::<Array>();
+        for &action in Action::ALL.iter() {
+            set(&mut keys[action.name()], key_list(self.keys.get(action)));
+        }
+        set(&mut keys["settings"], key_list(&self.keys.settings));
+        set(&mut keys["quit"], key_list(&self.keys.quit));
+
+        storage::write_atomic(path, &doc.to_string()).map_err(ConfigError::Io)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn save_keeps_comments_and_leaves_broken_files_alone() {
+        let path = env::temp_dir().join(format!("tetris-test-{}-config.toml", process::id()));
+        fs::write(&path, "# mine\n[handling]\ndas = 100 # fast\n").unwrap();
+        let mut config = Config::load(&path).unwrap();
+        config.handling.arr = 0;
+        config.save(&path).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(saved.contains("# mine\n[handling]\ndas = 100 # fast\n"), "{}", saved);
+        assert_eq!(Config::load(&path).unwrap().handling.arr, 0);
+
+        fs::write(&path, "[handling\ndas = 100\n").unwrap();
+        assert!(matches!(config.save(&path), Err(ConfigError::Parse(_))));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[handling\ndas = 100\n");
+        fs::remove_file(&path).unwrap();
+        let _ = fs::remove_file(storage::backup_path(&path));
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::board::Board;
+use crate::tetrimino::Tetrimino;
+
+/// Counts the movement keys pressed for each piece and compares them with
+/// the fewest that reach the same spot.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Finesse {
+    pub faults: u32,
+    // Pieces that were checked, which leaves out soft dropped ones.
+    pub judged: u32,
+    inputs: u32,
+    soft_dropped: bool,
+}
+
+impl Finesse {
+    pub fn new() -> Finesse {
+        Finesse { faults: 0, judged: 0, inputs: 0, soft_dropped: false }
+    }
+
+    /// The counts as values for a saved game.
+    pub fn to_fields(&self) -> Vec<String> {
+        vec![
+            self.faults.to_string(),
+            self.judged.to_string(),
+            self.inputs.to_string(),
+            self.soft_dropped.to_string(),
+        ]
+    }
+
+    pub fn from_fields(values: &[&str]) -> Option<Finesse> {
+        match *values {
+            [faults, judged, inputs, soft_dropped] => Some(Finesse {
+                faults: faults.parse().ok()?,
+                judged: judged.parse().ok()?,
+                inputs: inputs.parse().ok()?,
+                soft_dropped: soft_dropped.parse().ok()?,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn start_piece(&mut self) {
+        self.inputs = 0;
+        self.soft_dropped = false;
+    }
+
+    /// Counts a press of a move or rotate key. Auto-repeat is part of the
+    /// press, not an input of its own.
+    pub fn count_input(&mut self) {
+        self.inputs += 1;
+    }
+
+    // A soft dropped piece may have been tucked or spun under the stack,
+    // which the drop-from-above search can't judge.
+    pub fn soft_drop(&mut self) {
+        self.soft_dropped = true;
+    }
+
+    /// Judges a locked piece against `minimum` from `min_inputs`.
+    pub fn judge(&mut self, minimum: Option<u32>) {
+        if self.soft_dropped {
+            return;
+        }
+        if let Some(minimum) = minimum {
+            self.judged += 1;
+            if self.inputs > minimum {
+                self.faults += 1;
+            }
+        }
+    }
+}
+
+// The piece's cells relative to its highest row, which is what a hard drop
+// on an empty board depends on.
+fn footprint(tetrimino: &Tetrimino) -> Vec<(isize, isize)> {
+    let cells = tetrimino.cells();
+    let top = cells.iter().map(|&(_, y)| y).min().unwrap_or(0);
+    let mut footprint = cells.iter().map(|&(x, y)| (x, y - top)).collect::<Vec<_>>();
+    footprint.sort();
+    footprint
+}
+
+/// The fewest presses that take `spawned` to where `placed` is on an empty
+/// board `width` wide: taps, auto-repeat to a wall and rotations count one
+/// each. `None` if the spot can't be reached that way.
+pub fn min_inputs(spawned: Tetrimino, width: usize, placed: &Tetrimino) -> Option<u32> {
+    let scale = spawned.scale() as isize;
+    let board = Board::new(width, spawned.kind.def().size * spawned.scale());
+    let target = footprint(placed);
+
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::new();
+    seen.insert((spawned.rotation, spawned.x));
+    queue.push_back((spawned, 0));
+
+    while let Some((tetrimino, inputs)) = queue.pop_front() {
+        if footprint(&tetrimino) == target {
+            return Some(inputs);
+        }
+
+        let mut moves = Vec::new();
+        for &direction in [-scale, scale].iter() {
+            let mut tap = tetrimino;
+            let (x, y) = (tap.x + direction, tap.y);
+            if tap.change_position(&board, x, y) {
+                moves.push(tap);
+                let mut shift = tap;
+                loop {
+                    let (x, y) = (shift.x + direction, shift.y);
+                    if !shift.change_position(&board, x, y) {
+                        break;
+                    }
+                }
+                moves.push(shift);
+            }
+        }
+        let mut clockwise = tetrimino;
+        if clockwise.rotate(&board) {
+            moves.push(clockwise);
+        }
+        let mut counterclockwise = tetrimino;
+        if counterclockwise.rotate_counterclockwise(&board) {
+            moves.push(counterclockwise);
+        }
+
+        for next in moves {
+            if seen.insert((next.rotation, next.x)) {
+                queue.push_back((next, inputs + 1));
+            }
+        }
+    }
+    None
+}
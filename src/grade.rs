@@ -0,0 +1,119 @@
+use crate::tetris::FRAMES_PER_SECOND;
+
+pub const GRADE_NAMES: [&str; 18] = [
+    "9", "8", "7", "6", "5", "4", "3", "2", "1",
+    "S1", "S2", "S3", "S4", "S5", "S6", "S7", "S8", "S9",
+];
+
+pub const GRAND_MASTER: u32 = GRADE_NAMES.len() as u32;
+const POINTS_PER_GRADE: u32 = 100;
+// Finishing at S9 within this many seconds makes a grand master.
+const GRANDMASTER_SECONDS: u32 = 8 * 60;
+// Grade points for clearing 1 to 4 lines at once. Higher grades earn less
+// for small clears, so only tetrises keep a high grade climbing.
+const LINE_POINTS: [[u32; 4]; 4] = [
+    [10, 20, 40, 50],
+    [10, 15, 30, 40],
+    [5, 10, 20, 30],
+    [2, 12, 13, 30],
+];
+// Frames per point lost while no lines are cleared, by grade band.
+const DECAY_FRAMES: [u32; 4] = [125, 80, 45, 30];
+
+/// TGM style internal grade: line clears earn points, boosted by the
+/// section and by combos, and the points slowly drain while nothing is
+/// cleared. Every `POINTS_PER_GRADE` points is a grade up.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Grade {
+    pub grade: usize,
+    points: u32,
+    decay_timer: u32,
+    combo: u32,
+}
+
+fn band(grade: usize) -> usize {
+    match grade {
+        0..=2 => 0,
+        3..=6 => 1,
+        7..=11 => 2,
+        _ => 3,
+    }
+}
+
+impl Grade {
+    pub fn new() -> Grade {
+        Grade { grade: 0, points: 0, decay_timer: 0, combo: 0 }
+    }
+
+    /// The state as values for a saved game.
+    pub fn to_fields(&self) -> Vec<String> {
+        [self.grade as u32, self.points, self.decay_timer, self.combo].iter().map(|value| value.to_string()).collect()
+    }
+
+    pub fn from_fields(values: &[&str]) -> Option<Grade> {
+        match *values {
+            [grade, points, decay_timer, combo] => Some(Grade {
+                grade: grade.parse().ok().filter(|&grade| grade < GRADE_NAMES.len())?,
+                points: points.parse().ok()?,
+                decay_timer: decay_timer.parse().ok()?,
+                combo: combo.parse().ok()?,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Counts a locked piece that cleared `lines` lines in `section`.
+    pub fn award(&mut self, lines: u32, section: u32) {
+        if lines == 0 {
+            self.combo = 0;
+            return;
+        }
+        self.combo += 1;
+
+        let base = LINE_POINTS[band(self.grade)][lines.min(4) as usize - 1];
+        let combo_bonus = if lines > 1 { self.combo.min(4) } else { 1 };
+        self.points += base * combo_bonus * (1 + section / 3);
+        self.decay_timer = 0;
+
+        while self.points >= POINTS_PER_GRADE && self.grade + 1 < GRADE_NAMES.len() {
+            self.points -= POINTS_PER_GRADE;
+            self.grade += 1;
+        }
+        if self.grade + 1 == GRADE_NAMES.len() {
+            self.points = self.points.min(POINTS_PER_GRADE - 1);
+        }
+    }
+
+    /// Drains the points, once per frame.
+    pub fn tick(&mut self) {
+        if self.points == 0 {
+            return;
+        }
+        self.decay_timer += 1;
+        if self.decay_timer >= DECAY_FRAMES[band(self.grade)] {
+            self.decay_timer = 0;
+            self.points -= 1;
+        }
+    }
+
+    /// The grade as a number that orders grades, given how many frames the
+    /// game took if it was finished. A grand master is one past S9.
+    pub fn rank(&self, finished_in: Option<u32>) -> u32 {
+        match finished_in {
+            Some(frames) if self.grade + 1 == GRADE_NAMES.len()
+                && frames < GRANDMASTER_SECONDS * FRAMES_PER_SECOND => GRAND_MASTER,
+            _ => self.grade as u32,
+        }
+    }
+
+    /// The grade to show, given how many frames the game took if it was
+    /// finished.
+    pub fn name(&self, finished_in: Option<u32>) -> &'static str {
+        rank_name(self.rank(finished_in))
+    }
+}
+
+pub fn rank_name(rank: u32) -> &'static str {
+    GRADE_NAMES.get(rank as usize).cloned().unwrap_or("GM")
+}
@@ -0,0 +1,771 @@
+use crate::cli::Options;
+use crate::config::{self, Config};
+use crate::highscore::{LoadError, ScoreEntry, ScoreTable};
+use crate::input::{Action, Controller};
+use crate::keymap::{Binding, KeyMap};
+use crate::mode::{GameMode, Ranking};
+use crate::pieceset::PieceSet;
+use crate::puzzle::Puzzle;
+use crate::replay::{Playback, Replay, ReplayEvent, ReplayResult};
+use crate::savegame::SaveGame;
+use crate::screens::QuitChoice;
+use crate::tetris::{GameState, Tetris};
+use crate::{font, mode, screens, storage, tetris};
+use crate::exit_with_errors;
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::thread::sleep;
+use std::path::Path;
+
+use sdl3::pixels::Color;
+use sdl3::event::Event;
+use sdl3::keyboard::Keycode;
+use sdl3::rect::Rect;
+use sdl3::render::{Canvas, Texture, TextureCreator};
+use sdl3::video::{Window, WindowContext};
+
+const PALETTE: [(u8, u8, u8); 8] = [
+    (255, 69, 69),
+    (255, 220, 69),
+    (237, 150, 37),
+    (171, 99, 237),
+    (77, 149, 239),
+    (39, 218, 225),
+    (45, 216, 47),
+    // Garbage
+    (128, 128, 128),
+];
+
+// How long an invisible game's stack shows before the results.
+const REVEAL_DELAY: Duration = Duration::from_secs(2);
+const PLAYBACK_SPEEDS: [f64; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const PLAYBACK_SEEK_FRAMES: u32 = 5 * tetris::FRAMES_PER_SECOND;
+
+#[derive(Clone, Copy)]
+enum TextureColor {
+    Black,
+    White,
+    FromRGB(u8, u8, u8)
+}
+
+
+fn create_texture_rect<'a>(
+    canvas: &mut Canvas<Window>,
+    texture_creator: &'a TextureCreator<WindowContext>,
+    color: TextureColor,
+    width: u32,
+    height: u32
+) -> Option<Texture<'a>> {
+
+    if let Ok(mut rect_texture) = texture_creator.create_texture_target(None, width, height) {
+        canvas.with_texture_canvas(&mut rect_texture, |texture| {
+            match color {
+                TextureColor::Black => texture.set_draw_color(Color::RGB(0, 0, 0)),
+                TextureColor::White => texture.set_draw_color(Color::RGB(255, 255, 255)),
+                TextureColor::FromRGB(r, g, b) => texture.set_draw_color(Color::RGB(r, g, b))
+            }
+            texture.clear();
+        }).expect("Failed to color a texture");
+        Some(rect_texture)
+    } else {
+        None
+    }
+}
+
+// Falls back to the backup when the file is missing or damaged. `None`
+// when the file is there but can't be read, so it isn't saved over.
+fn load_scores(path: &Path) -> Option<ScoreTable> {
+    match ScoreTable::load(path) {
+        Ok(scores) if path.exists() => return Some(scores),
+        Ok(_) => {},
+        Err(LoadError::Corrupt(e)) => {
+            eprintln!("Could not load high scores from {}: {}", path.display(), e);
+            match storage::set_aside(path) {
+                Ok(aside) => eprintln!("The damaged file was moved to {}", aside.display()),
+                Err(e) => eprintln!("Could not move the damaged file aside: {}", e),
+            }
+        },
+        Err(LoadError::Io(e)) => {
+            eprintln!("Could not read high scores from {}: {}", path.display(), e);
+            eprintln!("Scores from this game will not be recorded");
+            return None;
+        },
+    }
+
+    let backup = storage::backup_path(path);
+    if !backup.exists() {
+        return Some(ScoreTable::new());
+    }
+    match ScoreTable::load(&backup) {
+        Ok(scores) => {
+            eprintln!("Using the backup at {}", backup.display());
+            Some(scores)
+        },
+        Err(e) => {
+            eprintln!("Could not load the backup at {} either: {}", backup.display(), e);
+            Some(ScoreTable::new())
+        }
+    }
+}
+
+fn save_scores(scores: &ScoreTable, path: &Path) {
+    if let Err(e) = scores.save(path) {
+        eprintln!("Could not save high scores to {}: {}", path.display(), e);
+    }
+}
+
+fn score_entry(tetris: &Tetris, name: String, replay: String) -> ScoreEntry {
+    ScoreEntry {
+        name,
+        score: tetris.score,
+        lines: tetris.number_of_lines,
+        level: tetris.current_level,
+        time_ms: mode::frames_to_ms(tetris.frames),
+        grade: tetris.grade_rank().unwrap_or(0),
+        replay,
+    }
+}
+
+// Races against the clock only count runs that reached the goal.
+fn counts_for_leaderboard(tetris: &Tetris) -> bool {
+    tetris.mode.has_leaderboard()
+        && (tetris.mode.ranking() != Ranking::Time || tetris.state == GameState::Finished)
+}
+
+fn record_score(
+    scores: &mut ScoreTable,
+    score_file: &Path,
+    tetris: &Tetris,
+    name: String,
+    replay: String
+) -> Option<usize> {
+    if !counts_for_leaderboard(tetris) {
+        return None;
+    }
+    let rank = scores.insert(&tetris.leaderboard(), score_entry(tetris, name, replay));
+
+    if rank.is_some() {
+        save_scores(scores, score_file);
+    }
+    rank
+}
+
+// Asks for a name when the game makes the leaderboard, however it ended.
+fn ask_name(
+    canvas: &mut Canvas<Window>,
+    event_pump: &mut sdl3::EventPump,
+    text_input: &sdl3::keyboard::TextInputUtil,
+    scores: &ScoreTable,
+    tetris: &Tetris
+) -> String {
+    let rank = if counts_for_leaderboard(tetris) {
+        scores.rank_of(&tetris.leaderboard(), &score_entry(tetris, String::new(), String::new()))
+    } else {
+        None
+    };
+    match rank {
+        Some(rank) => screens::enter_name(canvas, event_pump, text_input, rank),
+        None => String::new(),
+    }
+}
+
+// Unique enough for one player, and sorts by when the game started.
+fn replay_file_name(tetris: &Tetris) -> String {
+    let started = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{}-{}.replay", started.as_millis(), tetris.leaderboard())
+}
+
+// Stores the replay with the game's result and returns its file name, or
+// an empty name if it could not be written.
+fn save_replay(replay: &mut Replay, tetris: &Tetris, dir: &Path, file_name: &str) -> String {
+    replay.result = Some(ReplayResult::of(tetris));
+    let path = dir.join(file_name);
+    match replay.save(&path) {
+        Ok(()) => {
+            println!("Replay saved to {}", path.display());
+            file_name.to_string()
+        },
+        Err(e) => {
+            eprintln!("Could not save the replay to {}: {}", path.display(), e);
+            String::new()
+        },
+    }
+}
+
+// A saved game that can't be read is moved aside, like a damaged score
+// file, so it doesn't come up again.
+fn load_saved_game(path: &Path) -> Option<SaveGame> {
+    match SaveGame::load(path) {
+        Ok(saved) => saved,
+        Err(e) => {
+            eprintln!("Could not load the saved game from {}: {}", path.display(), e);
+            if let Err(e) = storage::set_aside(path) {
+                eprintln!("Could not move the damaged file aside: {}", e);
+            }
+            None
+        },
+    }
+}
+
+fn handle_events(
+    event_pump: &mut sdl3::EventPump,
+    keymap: &KeyMap,
+    inputs: &mut Vec<(Action, bool)>
+) -> Option<Binding> {
+    let mut request = None;
+
+    for event in event_pump.poll_iter() {
+        match event {
+            Event::Quit { .. } => request = Some(Binding::Quit),
+            Event::KeyDown { keycode: Some(keycode), repeat: false, .. } => {
+                match keymap.get(keycode) {
+                    Some(Binding::Game(action)) => inputs.push((action, true)),
+                    Some(binding) => request = Some(binding),
+                    None => {}
+                }
+            },
+            Event::KeyUp { keycode: Some(keycode), .. } => {
+                if let Some(Binding::Game(action)) = keymap.get(keycode) {
+                    inputs.push((action, false));
+                }
+            },
+            _ => {}
+        }
+    }
+    request
+}
+
+fn print_game_information(tetris: &Tetris, rank: Option<usize>) {
+    match (tetris.state, tetris.mode.puzzle_goal()) {
+        (GameState::Finished, Some(_)) => println!("Puzzle solved!"),
+        (_, Some(_)) => println!("Puzzle failed..."),
+        (GameState::Finished, None) => println!("Finished!"),
+        _ => println!("Game Over..."),
+    }
+    println!("Mode:             {}", tetris.mode.title());
+    if let Some(goal) = tetris.mode.puzzle_goal() {
+        println!("Goal:             {}", goal.description());
+    }
+    if tetris.mode.ranking() != Ranking::Score {
+        println!("Time:             {}", mode::format_time(mode::frames_to_ms(tetris.frames)));
+    }
+    println!("Score:            {}", tetris.score);
+    if let Some(grade) = tetris.grade_name() {
+        println!("Grade:            {}", grade);
+    }
+    println!("Finesse faults:   {} in {} pieces", tetris.finesse.faults, tetris.finesse.judged);
+    println!("Perfect clears:   {}", tetris.perfect_clears);
+    if tetris.mode == GameMode::PerfectClear {
+        println!("Best PC streak:   {}", tetris.best_pc_streak);
+    }
+    println!("Number of Lines:  {}", tetris.number_of_lines);
+    println!("Current Level: {}", tetris.current_level);
+    if let Some(rank) = rank {
+        println!("New Highscore! You placed #{}", rank);
+    }
+}
+
+fn draw_cells<'a, I: IntoIterator<Item = &'a [u8]>>(
+    canvas: &mut Canvas<Window>,
+    textures: &[Texture],
+    cells: I,
+    x: i32,
+    y: i32,
+    size: u32
+) {
+    for (line_nb, line) in cells.into_iter().enumerate() {
+        for (case_nb, case) in line.iter().enumerate() {
+            if *case == 0 { continue }
+            canvas.copy(
+                &textures[*case as usize - 1],
+                None,
+                Rect::new(
+                    x + case_nb as i32 * size as i32,
+                    y + line_nb as i32 * size as i32,
+                    size,
+                    size
+                )
+            ).expect("Couldn't copy texture into window");
+        }
+    }
+}
+
+fn draw_game(
+    canvas: &mut Canvas<Window>,
+    textures: &[Texture],
+    tetris: &Tetris,
+    config: &Config,
+    cell_size: u32,
+    grid_x: i32,
+    grid_y: i32
+) {
+    let text_scale = (cell_size / 16).max(1);
+    let text_color = Color::RGB(255, 255, 255);
+
+    if let Some(ref tetrimino) = tetris.current_tetrimino {
+        let piece = tetrimino.kind.color();
+        let cells = tetrimino.cells();
+
+        // The ghost would give away the shape of an invisible stack.
+        if config.visuals.ghost_piece && !tetris.variants.invisible {
+            if let Some(ghost_y) = tetris.ghost_y() {
+                let (r, g, b) = PALETTE[piece as usize - 1];
+                canvas.set_draw_color(Color::RGB(r, g, b));
+                for &(x, y) in cells.iter() {
+                    canvas.draw_rect(Rect::new(
+                        grid_x + x as i32 * cell_size as i32,
+                        grid_y + (y as i32 + ghost_y as i32 - tetrimino.y as i32) * cell_size as i32,
+                        cell_size,
+                        cell_size
+                    ).into()).expect("Couldn't draw ghost piece");
+                }
+            }
+        }
+
+        for &(x, y) in cells.iter() {
+            canvas.copy(
+                &textures[piece as usize - 1],
+                None,
+                Rect::new(
+                    grid_x + x as i32 * cell_size as i32,
+                    grid_y + y as i32 * cell_size as i32,
+                    cell_size,
+                    cell_size
+                )
+            ).expect("Couldn't copy texture into window");
+        }
+    }
+
+    if tetris.board_visible() {
+        draw_cells(canvas, textures, tetris.game_map.lines(), grid_x, grid_y, cell_size);
+    }
+
+    // Hold and statistics on the left, the next pieces on the right.
+    let preview_size = cell_size * 2 / 3;
+    let left_x = grid_x - 5 * cell_size as i32;
+    let right_x = grid_x + (tetris.width as u32 * cell_size) as i32 + cell_size as i32;
+
+    font::draw_text(canvas, "HOLD", left_x, grid_y, text_scale, text_color);
+    if let Some(piece) = tetris.hold {
+        draw_cells(canvas, textures, piece.preview().iter().map(Vec::as_slice), left_x, grid_y + cell_size as i32, preview_size);
+    }
+
+    let lines = match tetris.mode.line_goal() {
+        Some(goal) => format!("{}/{}", tetris.number_of_lines, goal),
+        None => tetris.number_of_lines.to_string(),
+    };
+    let mut stats = vec![
+        ("SCORE", tetris.score.to_string()),
+        ("LEVEL", tetris.current_level.to_string()),
+        ("LINES", lines),
+    ];
+    if let Some(grade) = tetris.grade_name() {
+        stats.push(("GRADE", grade.to_string()));
+    }
+    stats.push(("FAULTS", tetris.finesse.faults.to_string()));
+    if tetris.mode == GameMode::PerfectClear {
+        stats.push(("PC STREAK", tetris.pc_streak.to_string()));
+        stats.push(("BEST", tetris.best_pc_streak.to_string()));
+    }
+    if let Some(goal) = tetris.mode.garbage_goal() {
+        stats.push(("GARBAGE", format!("{}/{}", tetris.garbage_cleared, goal)));
+    }
+    if let Some(clock) = tetris.mode.clock(tetris.frames) {
+        stats.insert(0, ("TIME", mode::format_time(clock)));
+    }
+    for (stat_nb, (label, value)) in stats.iter().enumerate() {
+        let y = grid_y + (4 + stat_nb as i32 * 2) * cell_size as i32;
+        font::draw_text(canvas, label, left_x, y, text_scale, text_color);
+        font::draw_text(canvas, value, left_x, y + cell_size as i32 * 2 / 3, text_scale, text_color);
+    }
+
+    if config.preview_count > 0 {
+        font::draw_text(canvas, "NEXT", right_x, grid_y, text_scale, text_color);
+    }
+    // Room for the tallest piece of the set and a row between pieces.
+    let preview_rows = tetris.variants.piece_kinds().iter()
+        .map(|kind| kind.preview().iter().filter(|row| row.iter().any(|&case| case != 0)).count())
+        .max()
+        .unwrap_or(0) + 1;
+    for (piece_nb, &piece) in tetris.next_pieces.iter().take(config.preview_count).enumerate() {
+        draw_cells(
+            canvas,
+            textures,
+            piece.preview().iter().map(Vec::as_slice),
+            right_x,
+            grid_y + cell_size as i32 + (piece_nb * preview_rows) as i32 * preview_size as i32,
+            preview_size
+        );
+    }
+}
+
+// The textures the board is drawn with, and where it is.
+struct Board<'a> {
+    grid: Texture<'a>,
+    border: Texture<'a>,
+    cells: Vec<Texture<'a>>,
+    x: i32,
+    y: i32,
+    // The configured cell size times `--scale`, which is never saved.
+    cell_size: u32,
+}
+
+fn draw_frame(canvas: &mut Canvas<Window>, board: &Board, tetris: &Tetris, config: &Config) {
+    let cell_size = board.cell_size;
+    let board_width = tetris.width as u32;
+    let board_height = tetris.height as u32;
+
+    canvas.set_draw_color(Color::RGB(255, 0, 0));
+    canvas.clear();
+    canvas.copy(
+        &board.border,
+        None,
+        Rect::new(
+            board.x - 10,
+            board.y - 10,
+            cell_size * board_width + 20,
+            cell_size * board_height + 20)
+    ).expect("Could not draw border");
+    canvas.copy(
+        &board.grid,
+        None,
+        Rect::new(
+            board.x,
+            board.y,
+            cell_size * board_width,
+            cell_size * board_height)
+    ).expect("Could not draw grid");
+
+    draw_game(canvas, &board.cells, tetris, config, board.cell_size, board.x, board.y);
+}
+
+/// Shows a replay with pause, speed, frame step and seek controls, and
+/// checks that it ends with the recorded result.
+fn run_playback(
+    canvas: &mut Canvas<Window>,
+    event_pump: &mut sdl3::EventPump,
+    board: &Board,
+    config: &Config,
+    mut playback: Playback
+) {
+    let text_scale = (board.cell_size / 16).max(1);
+    let frame = Duration::from_secs(1) / tetris::FRAMES_PER_SECOND;
+    let mut speed = PLAYBACK_SPEEDS.iter().position(|&speed| speed == 1.0).unwrap_or(0);
+    let mut paused = false;
+    let mut checked = false;
+    let mut last_update = Instant::now();
+    let mut owed = 0.0;
+
+    loop {
+        for event in event_pump.poll_iter() {
+            let Event::KeyDown { keycode: Some(keycode), .. } = event else {
+                if let Event::Quit { .. } = event {
+                    return;
+                }
+                continue;
+            };
+            let frames = playback.tetris.frames;
+            match keycode {
+                Keycode::Escape => return,
+                Keycode::Space => paused = !paused,
+                Keycode::Up => speed = (speed + 1).min(PLAYBACK_SPEEDS.len() - 1),
+                Keycode::Down => speed = speed.saturating_sub(1),
+                Keycode::Period => {
+                    paused = true;
+                    playback.step();
+                },
+                Keycode::Left => playback.seek(frames.saturating_sub(PLAYBACK_SEEK_FRAMES)),
+                Keycode::Right => playback.seek(frames + PLAYBACK_SEEK_FRAMES),
+                Keycode::Home => playback.seek(0),
+                _ => {}
+            }
+        }
+
+        let now = Instant::now();
+        if !paused {
+            owed += (now - last_update).as_secs_f64() * PLAYBACK_SPEEDS[speed] / frame.as_secs_f64();
+            while owed >= 1.0 && !playback.is_over() {
+                playback.step();
+                owed -= 1.0;
+            }
+        }
+        owed = owed.min(1.0);
+        last_update = now;
+
+        draw_frame(canvas, board, &playback.tetris, config);
+        let status = format!(
+            "REPLAY {}X{}  {}",
+            PLAYBACK_SPEEDS[speed],
+            if paused { " PAUSED" } else { "" },
+            mode::format_time(mode::frames_to_ms(playback.tetris.frames))
+        );
+        font::draw_text(canvas, &status, board.x, board.y - 10 - 8 * text_scale as i32 * 2, text_scale, Color::RGB(255, 255, 255));
+
+        if playback.is_over() {
+            let verdict = match (playback.matches_result(), playback.replay.result) {
+                (Some(false), Some(result)) => {
+                    if !checked {
+                        eprintln!(
+                            "tetris: warning: replay desync, recorded score {} and {} lines, played back {} and {} lines",
+                            result.score, result.lines, playback.tetris.score, playback.tetris.number_of_lines
+                        );
+                    }
+                    format!("DESYNC! RECORDED SCORE {}", result.score)
+                },
+                (Some(_), _) => "END OF REPLAY, SCORE VERIFIED".to_string(),
+                (None, _) => "END OF REPLAY".to_string(),
+            };
+            checked = true;
+            font::draw_text(canvas, &verdict, board.x, board.y - 10 - 8 * text_scale as i32, text_scale, Color::RGB(255, 220, 69));
+        }
+        canvas.present();
+
+        sleep(Duration::from_millis(1));
+    }
+}
+
+fn apply_config(config: &Config, tetris: &mut Tetris, controller: &mut Controller) {
+    tetris.lock_delay = tetris::ms_to_frames(config.handling.lock_delay);
+    controller.set_handling(&config.handling);
+}
+
+/// Plays a game, or shows a replay, in a window until the player quits.
+pub fn run(options: Options) {
+    let score_file = storage::score_file(options.score_file.clone());
+    let config_file = config::config_file(options.config.clone());
+    let mut config = Config::load(&config_file)
+        .unwrap_or_else(|e| exit_with_errors(&config_file.display().to_string(), &e.to_string()));
+    let keymap = KeyMap::new(&config.keys)
+        .unwrap_or_else(|errors| exit_with_errors(&config_file.display().to_string(), &errors.join("\n  ")));
+
+    let playback = options.replay.as_ref().map(|path| {
+        Playback::new(Replay::load(path).unwrap_or_else(|e| exit_with_errors(&path.display().to_string(), &e.to_string())))
+    });
+    let save_file = storage::save_file(&score_file);
+    let saved = if playback.is_none() && options.puzzle.is_none() {
+        load_saved_game(&save_file)
+    } else {
+        None
+    };
+    let mut variants = options.variants;
+    if let Some(ref source) = options.pieces {
+        let piece_set = PieceSet::load(source).unwrap_or_else(|e| exit_with_errors(source, &e.to_string()));
+        variants.piece_set = Some(piece_set);
+        if options.board_width < variants.min_width() {
+            exit_with_errors(source, &format!("its pieces need a --width of at least {}", variants.min_width()));
+        }
+    }
+    let seed = options.seed.unwrap_or_else(rand::random);
+    let mut tetris = match (&playback, &options.puzzle) {
+        (Some(playback), _) => playback.tetris.clone(),
+        (None, Some(path)) => {
+            let puzzle = Puzzle::load(path)
+                .unwrap_or_else(|e| exit_with_errors(&path.display().to_string(), &e.to_string()));
+            Tetris::with_setup(GameMode::Puzzle { goal: puzzle.goal }, variants, puzzle.board, &puzzle.pieces)
+        },
+        (None, None) => Tetris::new(options.mode, variants, options.board_width, options.board_height, seed),
+    };
+
+    let sdl_context = sdl3::init().expect("SDL initialization failed");
+    let video_subsystem = sdl_context.video().expect("Could not get SDL video subsystem");
+    let mut event_pump = sdl_context.event_pump().expect("Could not get SDL event pump");
+
+    let cell_size = (config.visuals.cell_size as f32 * options.scale).round() as u32;
+    let window_size = |tetris: &Tetris| (cell_size * (tetris.width as u32 + 12), cell_size * (tetris.height as u32 + 4));
+
+    tetris.current_level = options.level;
+    tetris.scoring = config.scoring;
+    let mut controller = Controller::new(&config.handling);
+    apply_config(&config, &mut tetris, &mut controller);
+    let mut replay = Replay::record(&tetris, seed, &config.handling);
+    let replay_dir = storage::replay_dir(&score_file);
+    let mut replay_name = replay_file_name(&tetris);
+
+    let (window_width, window_height) = window_size(saved.as_ref().map_or(&tetris, |saved| &saved.tetris));
+    let mut window_builder = video_subsystem.window("Tetris", window_width, window_height);
+    window_builder.position_centered().opengl();
+    if options.fullscreen || config.visuals.fullscreen {
+        window_builder.fullscreen();
+    }
+    let window = window_builder.build().expect("Could not create window");
+
+    let mut canvas = window.into_canvas();
+
+    if let Some(saved) = saved {
+        match screens::offer_resume(&mut canvas, &mut event_pump, &saved.tetris) {
+            Some(true) => {
+                tetris = saved.tetris;
+                replay = saved.replay;
+                replay_name = saved.replay_name;
+                apply_config(&config, &mut tetris, &mut controller);
+                // The keys held when the game was saved are up now.
+                replay.push(tetris.frames, ReplayEvent::Handling(config.handling.clone()));
+            },
+            Some(false) => {
+                let (width, height) = window_size(&tetris);
+                if let Err(e) = canvas.window_mut().set_size(width, height) {
+                    eprintln!("Could not resize the window: {}", e);
+                }
+            },
+            None => return,
+        }
+        if let Err(e) = storage::remove(&save_file) {
+            eprintln!("Could not remove the saved game at {}: {}", save_file.display(), e);
+        }
+    }
+    let board_width = tetris.width as u32;
+    let board_height = tetris.height as u32;
+
+    let (width, height) = canvas.output_size().expect("Could not get window size");
+    let grid_x = (width - cell_size * board_width) as i32 / 2;
+    let grid_y = (height - cell_size * board_height) as i32 / 2;
+
+    let texture_creator = canvas.texture_creator();
+
+    let board = Board {
+        grid: create_texture_rect(
+            &mut canvas,
+            &texture_creator,
+            TextureColor::Black,
+            cell_size * board_width,
+            cell_size * board_height
+        ).expect("Could not create grid texture"),
+        border: create_texture_rect(
+            &mut canvas,
+            &texture_creator,
+            TextureColor::White,
+            cell_size * board_width + 20,
+            cell_size * board_height + 20
+        ).expect("Could not create border texture"),
+        cells: PALETTE.iter().map(|&(r, g, b)| {
+            create_texture_rect(
+                &mut canvas,
+                &texture_creator,
+                TextureColor::FromRGB(r, g, b),
+                cell_size,
+                cell_size
+            ).unwrap()
+        }).collect(),
+        x: grid_x,
+        y: grid_y,
+        cell_size,
+    };
+
+    if let Some(playback) = playback {
+        run_playback(&mut canvas, &mut event_pump, &board, &config, playback);
+        return;
+    }
+
+    let frame = Duration::from_secs(1) / tetris::FRAMES_PER_SECOND;
+    let mut last_frame = Instant::now();
+    let mut inputs = Vec::new();
+
+    loop {
+        match handle_events(&mut event_pump, &keymap, &mut inputs) {
+            Some(Binding::Quit) => {
+                inputs.clear();
+                controller.release_all();
+                let choice = screens::confirm_quit(&mut canvas, &mut event_pump);
+                if choice == QuitChoice::KeepPlaying {
+                    replay.push(tetris.frames, ReplayEvent::Handling(config.handling.clone()));
+                    last_frame = Instant::now();
+                    continue;
+                }
+                if choice == QuitChoice::SaveAndQuit {
+                    let saved = SaveGame { tetris, replay, replay_name };
+                    match saved.save(&save_file) {
+                        Ok(()) => {
+                            println!("Game saved to {}", save_file.display());
+                            break
+                        },
+                        Err(e) => eprintln!("Could not save the game to {}: {}", save_file.display(), e),
+                    }
+                    tetris = saved.tetris;
+                    replay = saved.replay;
+                    replay_name = saved.replay_name;
+                }
+                let mut scores = load_scores(&score_file);
+                let name = match scores {
+                    Some(ref scores) => ask_name(&mut canvas, &mut event_pump, &video_subsystem.text_input(), scores, &tetris),
+                    None => String::new(),
+                };
+                let replay_name = save_replay(&mut replay, &tetris, &replay_dir, &replay_name);
+                let rank = scores.as_mut().and_then(|scores| record_score(scores, &score_file, &tetris, name, replay_name));
+                print_game_information(&tetris, rank);
+                break
+            },
+            Some(Binding::Settings) => {
+                inputs.clear();
+                controller.release_all();
+                if let Some(edited) = screens::settings_menu(&mut canvas, &mut event_pump, &config) {
+                    if let Err(e) = edited.save(&config_file) {
+                        eprintln!("Could not save settings to {}: {}", config_file.display(), e);
+                    }
+                    config = edited;
+                    apply_config(&config, &mut tetris, &mut controller);
+                }
+                // Also recorded when nothing changed, for the released keys.
+                replay.push(tetris.frames, ReplayEvent::Handling(config.handling.clone()));
+                last_frame = Instant::now();
+            },
+            _ => {}
+        }
+
+        // The game runs in fixed frames so timings don't depend on how fast
+        // the window is redrawn.
+        while tetris.state == GameState::Playing && last_frame.elapsed() >= frame {
+            last_frame += frame;
+            for (action, pressed) in inputs.drain(..) {
+                if pressed {
+                    replay.push(tetris.frames, ReplayEvent::Press(action));
+                    controller.press(action, &mut tetris);
+                } else {
+                    replay.push(tetris.frames, ReplayEvent::Release(action));
+                    controller.release(action);
+                }
+            }
+            controller.update(&mut tetris);
+            tetris.step();
+        }
+
+        draw_frame(&mut canvas, &board, &tetris, &config);
+
+        // Present the updated canvas
+        canvas.present();
+
+        if tetris.state != GameState::Playing {
+            // Give the player a look at the stack they couldn't see.
+            if tetris.variants.invisible {
+                sleep(REVEAL_DELAY);
+                event_pump.poll_iter().for_each(drop);
+            }
+            let mut scores = load_scores(&score_file);
+            let leaderboard = tetris.leaderboard();
+            let name = match scores {
+                Some(ref scores) => ask_name(&mut canvas, &mut event_pump, &video_subsystem.text_input(), scores, &tetris),
+                None => String::new(),
+            };
+            let saved_replay = save_replay(&mut replay, &tetris, &replay_dir, &replay_name);
+            let rank = scores.as_mut().and_then(|scores| record_score(scores, &score_file, &tetris, name, saved_replay));
+            print_game_information(&tetris, rank);
+
+            let offer_continue = tetris.state == GameState::Finished
+                && matches!(tetris.mode, GameMode::Marathon { .. });
+            if screens::show_game_over(&mut canvas, &mut event_pump, &tetris, scores.as_ref().and_then(|scores| scores.leaderboard(&leaderboard)), rank, offer_continue) {
+                tetris.continue_endless();
+                replay.push(tetris.frames, ReplayEvent::Continue);
+                // The won game's replay stays linked to its score, the
+                // endless run is saved to a file of its own.
+                replay_name = replay_file_name(&tetris);
+                inputs.clear();
+                controller.release_all();
+                last_frame = Instant::now();
+                continue
+            }
+            break
+        }
+
+        sleep(Duration::from_millis(1));
+    }
+
+}
@@ -0,0 +1,291 @@
+use std::collections::BTreeMap;
+use std::cmp::Ordering;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use crate::grade;
+use crate::mode::{self, Ranking};
+use crate::storage;
+
+pub const NUM_HIGHSCORES: usize = 5;
+pub const MAX_NAME_LEN: usize = 12;
+
+const HEADER: &str = "tetris-scores 4";
+// Same layout without the grade column, then also without the replay
+// column, and also without the time column.
+const HEADER_V3: &str = "tetris-scores 3";
+const HEADER_V2: &str = "tetris-scores 2";
+const HEADER_V1: &str = "tetris-scores 1";
+
+// Names are stored tab-separated and drawn with the built-in font, so only
+// printable ASCII without tabs is accepted.
+pub fn is_valid_name_char(c: char) -> bool {
+    c.is_ascii_graphic() || c == ' '
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScoreEntry {
+    pub name: String,
+    pub score: u32,
+    pub lines: u32,
+    pub level: u32,
+    // Play time in milliseconds.
+    pub time_ms: u32,
+    // `Grade::rank` in modes with a grade, 0 in the others.
+    pub grade: u32,
+    // File name of the game's replay in the replay directory, empty if
+    // there is none.
+    pub replay: String,
+}
+
+impl ScoreEntry {
+    // By score, higher score wins, then the same score reached with fewer
+    // lines. By time, the faster run wins, then the higher score. By grade,
+    // the higher grade wins, then the run with more lines, then the faster
+    // one. An entry that ties is placed after the existing one, so whoever
+    // got there first keeps the better rank.
+    fn ranks_before(&self, other: &ScoreEntry, ranking: Ranking) -> bool {
+        let order = match ranking {
+            Ranking::Score => other.score.cmp(&self.score).then(self.lines.cmp(&other.lines)),
+            Ranking::Time => self.time_ms.cmp(&other.time_ms).then(other.score.cmp(&self.score)),
+            Ranking::Grade => other.grade.cmp(&self.grade)
+                .then(other.lines.cmp(&self.lines))
+                .then(self.time_ms.cmp(&other.time_ms)),
+        };
+        order == Ordering::Less
+    }
+}
+
+pub struct Leaderboard {
+    entries: Vec<ScoreEntry>,
+    ranking: Ranking,
+}
+
+impl Leaderboard {
+    pub fn new(ranking: Ranking) -> Leaderboard {
+        Leaderboard { entries: Vec::new(), ranking }
+    }
+
+    pub fn ranking(&self) -> Ranking {
+        self.ranking
+    }
+
+    pub fn entries(&self) -> &[ScoreEntry] {
+        &self.entries
+    }
+
+    /// The 1-based rank `entry` would get, or `None` if it does not make the
+    /// top `NUM_HIGHSCORES`.
+    pub fn rank_of(&self, entry: &ScoreEntry) -> Option<usize> {
+        let index = self.entries.iter()
+            .position(|existing| entry.ranks_before(existing, self.ranking))
+            .unwrap_or(self.entries.len());
+
+        if index < NUM_HIGHSCORES {
+            Some(index + 1)
+        } else {
+            None
+        }
+    }
+
+    pub fn insert(&mut self, entry: ScoreEntry) -> Option<usize> {
+        let rank = self.rank_of(&entry)?;
+        self.entries.insert(rank - 1, entry);
+        self.entries.truncate(NUM_HIGHSCORES);
+        Some(rank)
+    }
+}
+
+/// One leaderboard per game mode, keyed by `GameMode::leaderboard`.
+#[derive(Default)]
+pub struct ScoreTable {
+    boards: BTreeMap<String, Leaderboard>,
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Corrupt(ParseError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Io(ref e) => write!(f, "{}", e),
+            LoadError::Corrupt(ref e) => write!(f, "corrupt score file, {}", e),
+        }
+    }
+}
+
+impl ScoreTable {
+    pub fn new() -> ScoreTable {
+        ScoreTable { boards: BTreeMap::new() }
+    }
+
+    pub fn leaderboard(&self, mode: &str) -> Option<&Leaderboard> {
+        self.boards.get(mode)
+    }
+
+    pub fn rank_of(&self, mode: &str, entry: &ScoreEntry) -> Option<usize> {
+        match self.boards.get(mode) {
+            Some(board) => board.rank_of(entry),
+            None => Some(1),
+        }
+    }
+
+    pub fn insert(&mut self, mode: &str, entry: ScoreEntry) -> Option<usize> {
+        self.boards.entry(mode.to_string())
+            .or_insert_with(|| Leaderboard::new(mode::leaderboard_ranking(mode)))
+            .insert(entry)
+    }
+
+    /// Loads the table at `path`. A missing file is an empty table.
+    pub fn load(path: &Path) -> Result<ScoreTable, LoadError> {
+        match storage::read_file(path).map_err(LoadError::Io)? {
+            Some(content) => ScoreTable::parse(&content).map_err(LoadError::Corrupt),
+            None => Ok(ScoreTable::new()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        storage::write_atomic(path, &self.to_string())
+    }
+
+    pub fn parse(content: &str) -> Result<ScoreTable, ParseError> {
+        let mut lines = content.lines().enumerate();
+
+        let columns = match lines.next() {
+            Some((_, HEADER)) => 8,
+            Some((_, HEADER_V3)) => 7,
+            Some((_, HEADER_V2)) => 6,
+            Some((_, HEADER_V1)) => 5,
+            Some((_, first)) => return ScoreTable::parse_legacy(first),
+            None => return Ok(ScoreTable::new()),
+        };
+
+        let mut table = ScoreTable::new();
+        for (line_nb, line) in lines {
+            if line.is_empty() {
+                continue;
+            }
+            let error = |reason: &str| ParseError { line: line_nb + 1, reason: reason.to_string() };
+
+            let fields = line.splitn(columns, '\t').collect::<Vec<_>>();
+            if fields.len() != columns {
+                return Err(error(match columns {
+                    8 => "expected mode, score, lines, level, time, grade, replay and name",
+                    7 => "expected mode, score, lines, level, time, replay and name",
+                    6 => "expected mode, score, lines, level, time and name",
+                    _ => "expected mode, score, lines, level and name",
+                }));
+            }
+            let number = |field: &str| field.parse::<u32>().map_err(|_| error("invalid number"));
+
+            let entry = ScoreEntry {
+                score: number(fields[1])?,
+                lines: number(fields[2])?,
+                level: number(fields[3])?,
+                time_ms: if columns >= 6 { number(fields[4])? } else { 0 },
+                grade: match columns {
+                    8 => Some(number(fields[5])?).filter(|&grade| grade <= grade::GRAND_MASTER)
+                        .ok_or_else(|| error("invalid grade"))?,
+                    _ => 0,
+                },
+                replay: if columns >= 7 { fields[columns - 2].to_string() } else { String::new() },
+                name: fields[columns - 1].to_string(),
+            };
+            table.insert(fields[0], entry);
+        }
+        Ok(table)
+    }
+
+    // Files written before the leaderboard existed hold a line of scores and
+    // a line of line counts, each sorted on its own, so only the scores are
+    // worth keeping.
+    fn parse_legacy(scores: &str) -> Result<ScoreTable, ParseError> {
+        let mut table = ScoreTable::new();
+        for score in scores.split_whitespace() {
+            let score = score.parse::<u32>().map_err(|_| ParseError {
+                line: 1,
+                reason: "unrecognized score file format".to_string(),
+            })?;
+            table.insert("marathon", ScoreEntry { name: String::new(), score, lines: 0, level: 1, time_ms: 0, grade: 0, replay: String::new() });
+        }
+        Ok(table)
+    }
+}
+
+impl fmt::Display for ScoreTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        for (mode, board) in &self.boards {
+            for entry in board.entries() {
+                writeln!(
+                    f, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                    mode, entry.score, entry.lines, entry.level, entry.time_ms, entry.grade, entry.replay, entry.name
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(grade: u32, lines: u32, time_ms: u32) -> ScoreEntry {
+        ScoreEntry { name: String::new(), score: 0, lines, level: 1, time_ms, grade, replay: String::new() }
+    }
+
+    #[test]
+    fn master_ranks_by_grade_then_lines_then_time() {
+        let mut table = ScoreTable::new();
+        table.insert("master", entry(5, 100, 300_000));
+        table.insert("master", entry(9, 40, 200_000));
+        table.insert("master", entry(5, 100, 250_000));
+        table.insert("master", entry(5, 60, 100_000));
+        table.insert("master", entry(grade::GRAND_MASTER, 100, 400_000));
+
+        let ranked = table.leaderboard("master").unwrap().entries().iter()
+            .map(|entry| (entry.grade, entry.lines, entry.time_ms))
+            .collect::<Vec<_>>();
+        assert_eq!(ranked, [
+            (grade::GRAND_MASTER, 100, 400_000),
+            (9, 40, 200_000),
+            (5, 100, 250_000),
+            (5, 100, 300_000),
+            (5, 60, 100_000),
+        ]);
+        assert_eq!(table.rank_of("master+big", &entry(0, 0, 0)), Some(1));
+    }
+
+    #[test]
+    fn grades_survive_saving() {
+        let old = "tetris-scores 3\nmaster\t900\t30\t1\t60000\tgame.replay\tme\n";
+        let table = ScoreTable::parse(old).unwrap();
+        let loaded = ScoreTable::parse(&table.to_string()).unwrap();
+        let board = loaded.leaderboard("master").unwrap();
+        assert_eq!(board.ranking(), Ranking::Grade);
+        assert_eq!(board.entries(), [ScoreEntry { name: "me".to_string(), score: 900, replay: "game.replay".to_string(), ..entry(0, 30, 60_000) }]);
+
+        let mut table = ScoreTable::new();
+        table.insert("master", entry(12, 70, 1000));
+        let loaded = ScoreTable::parse(&table.to_string()).unwrap();
+        assert_eq!(loaded.leaderboard("master").unwrap().entries()[0].grade, 12);
+        assert!(ScoreTable::parse("tetris-scores 4\nmaster\t0\t0\t1\t0\t99\t\t\n").is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use crate::config::Handling;
+use crate::tetris::{self, Tetris};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    Left,
+    Right,
+    SoftDrop,
+    HardDrop,
+    RotateCw,
+    RotateCcw,
+    Hold,
+}
+
+impl Action {
+    pub const ALL: [Action; 7] = [
+        Action::Left,
+        Action::Right,
+        Action::SoftDrop,
+        Action::HardDrop,
+        Action::RotateCw,
+        Action::RotateCcw,
+        Action::Hold,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Left => "left",
+            Action::Right => "right",
+            Action::SoftDrop => "soft_drop",
+            Action::HardDrop => "hard_drop",
+            Action::RotateCw => "rotate_cw",
+            Action::RotateCcw => "rotate_ccw",
+            Action::Hold => "hold",
+        }
+    }
+}
+
+/// Turns presses and releases into piece movement, including auto-repeat
+/// for held keys. All timings are in frames.
+#[derive(Clone)]
+pub struct Controller {
+    das: u32,
+    arr: u32,
+    soft_drop: u32,
+    held: [bool; 7],
+    // The most recently pressed horizontal direction wins while both are held.
+    shift: isize,
+    shift_timer: u32,
+    soft_drop_timer: u32,
+}
+
+impl Controller {
+    pub fn new(handling: &Handling) -> Controller {
+        let mut controller = Controller {
+            das: 0,
+            arr: 0,
+            soft_drop: 0,
+            held: [false; 7],
+            shift: 0,
+            shift_timer: 0,
+            soft_drop_timer: 0,
+        };
+        controller.set_handling(handling);
+        controller
+    }
+
+    pub fn set_handling(&mut self, handling: &Handling) {
+        self.das = tetris::ms_to_frames(handling.das);
+        self.arr = tetris::ms_to_frames(handling.arr);
+        self.soft_drop = tetris::ms_to_frames(handling.soft_drop);
+    }
+
+    fn is_held(&self, action: Action) -> bool {
+        self.held[action as usize]
+    }
+
+    pub fn press(&mut self, action: Action, tetris: &mut Tetris) {
+        if self.is_held(action) {
+            return;
+        }
+        self.held[action as usize] = true;
+
+        if let Action::Left | Action::Right | Action::RotateCw | Action::RotateCcw = action {
+            tetris.finesse.count_input();
+        }
+        match action {
+            Action::Left | Action::Right => {
+                self.shift = if action == Action::Left { -1 } else { 1 };
+                self.shift_timer = 0;
+                tetris.move_horizontal(self.shift);
+            },
+            Action::SoftDrop => {
+                self.soft_drop_timer = 0;
+                self.drop_soft(tetris);
+            },
+            Action::HardDrop => tetris.hard_drop(),
+            Action::RotateCw => { tetris.rotate(true); },
+            Action::RotateCcw => { tetris.rotate(false); },
+            Action::Hold => tetris.hold(),
+        }
+    }
+
+    pub fn release(&mut self, action: Action) {
+        self.held[action as usize] = false;
+
+        let direction = match action {
+            Action::Left => -1,
+            Action::Right => 1,
+            _ => return,
+        };
+        if self.shift == direction {
+            // Fall back to the other direction if it is still held.
+            let other = if direction == -1 { Action::Right } else { Action::Left };
+            self.shift = if self.is_held(other) { -direction } else { 0 };
+            self.shift_timer = 0;
+        }
+    }
+
+    pub fn release_all(&mut self) {
+        self.held = [false; 7];
+        self.shift = 0;
+    }
+
+    fn drop_soft(&mut self, tetris: &mut Tetris) {
+        if self.soft_drop == 0 {
+            while tetris.soft_drop() {}
+        } else {
+            tetris.soft_drop();
+        }
+    }
+
+    /// Repeats held movement; call once per frame before `Tetris::step`.
+    pub fn update(&mut self, tetris: &mut Tetris) {
+        if self.shift != 0 {
+            self.shift_timer += 1;
+            if self.shift_timer >= self.das {
+                if self.arr == 0 {
+                    while tetris.move_horizontal(self.shift) {}
+                } else if (self.shift_timer - self.das).is_multiple_of(self.arr) {
+                    tetris.move_horizontal(self.shift);
+                }
+            }
+        }
+
+        if self.is_held(Action::SoftDrop) {
+            self.soft_drop_timer += 1;
+            if self.soft_drop == 0 || self.soft_drop_timer.is_multiple_of(self.soft_drop) {
+                self.drop_soft(tetris);
+            }
+        }
+    }
+}
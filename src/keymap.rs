@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+
+use sdl3::keyboard::Keycode;
+
+use crate::config::KeyBindings;
+use crate::input::Action;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Binding {
+    Game(Action),
+    Settings,
+    Quit,
+}
+
+pub struct KeyMap {
+    keys: HashMap<Keycode, Binding>,
+}
+
+impl KeyMap {
+    /// Resolves the SDL key names in `bindings`, reporting every unknown or
+    /// doubly bound key.
+    pub fn new(bindings: &KeyBindings) -> Result<KeyMap, Vec<String>> {
+        let mut all = Action::ALL.iter()
+            .map(|&action| (action.name(), Binding::Game(action), bindings.get(action)))
+            .collect::<Vec<_>>();
+        all.push(("settings", Binding::Settings, &bindings.settings));
+        all.push(("quit", Binding::Quit, &bindings.quit));
+
+        let mut keys = HashMap::new();
+        let mut errors = Vec::new();
+        for (setting, binding, names) in all {
+            for name in names {
+                match Keycode::from_name(name) {
+                    Some(keycode) => {
+                        if let Some(previous) = keys.insert(keycode, binding) {
+                            if previous != binding {
+                                errors.push(format!("keys.{}: \"{}\" is already bound", setting, name));
+                            }
+                        }
+                    },
+                    None => errors.push(format!("keys.{}: unknown key \"{}\"", setting, name)),
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(KeyMap { keys })
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn get(&self, keycode: Keycode) -> Option<Binding> {
+        self.keys.get(&keycode).cloned()
+    }
+}
@@ -0,0 +1,27 @@
+// The game rules and everything around them that works without a window,
+// so the replay verifier and the tests build without SDL.
+
+extern crate rand;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+extern crate toml_edit;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
+pub mod board;
+pub mod cli;
+pub mod config;
+pub mod finesse;
+pub mod grade;
+pub mod highscore;
+pub mod input;
+pub mod mode;
+pub mod pieceset;
+pub mod puzzle;
+pub mod randomizer;
+pub mod replay;
+pub mod savegame;
+pub mod storage;
+pub mod tetrimino;
+pub mod tetris;
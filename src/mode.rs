@@ -0,0 +1,403 @@
+use crate::pieceset::PieceSet;
+use crate::tetrimino::PieceKind;
+use crate::tetris::{FRAMES_PER_SECOND, G, MAX_LOCK_RESETS};
+
+pub const MARATHON_LINES: [u32; 2] = [150, 200];
+pub const SPRINT_LINES: u32 = 40;
+pub const MAX_LINE_GOAL: u32 = 999;
+pub const MAX_MESSINESS: u32 = 100;
+pub const DIG_LINES: u32 = 10;
+pub const DIG_MESSINESS: u32 = 30;
+pub const SURVIVAL_MESSINESS: u32 = 50;
+// Survival starts with a garbage row every 8 seconds and speeds up by half
+// a second every 30 seconds, down to one row a second.
+const SURVIVAL_START_INTERVAL: u32 = 8000;
+const SURVIVAL_SPEEDUP: u32 = 500;
+const SURVIVAL_SPEEDUP_EVERY: u32 = 30;
+const SURVIVAL_MIN_INTERVAL: u32 = 1000;
+pub const MASTER_LINES: u32 = 100;
+const MASTER_SECTION_LINES: u32 = 10;
+// Entry delay and lock delay in frames for each master section.
+const MASTER_ARE: [u32; 10] = [25, 25, 25, 25, 25, 16, 12, 12, 6, 6];
+const MASTER_LOCK_DELAY: [u32; 10] = [30, 30, 30, 30, 30, 30, 30, 30, 30, 17];
+pub const ULTRA_MINUTES: [u32; 2] = [3, 2];
+// Keeps the time limit, in frames, well inside a u32.
+const MAX_ULTRA_MINUTES: u32 = 60;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum GameMode {
+    // Clear `lines` lines, or play until the stack tops out without a goal.
+    Marathon { lines: Option<u32> },
+    // Clear `lines` lines as fast as possible.
+    Sprint { lines: u32 },
+    // Score as much as possible before the clock runs out.
+    Ultra { minutes: u32 },
+    // Clear `lines` garbage lines as fast as possible. `messiness` is the
+    // percent chance that a garbage row's hole moves.
+    Dig { lines: u32, messiness: u32 },
+    // Garbage rises from below, faster and faster, until the stack tops out.
+    Survival { messiness: u32 },
+    // 20G from the first piece, with shrinking delays and a grade.
+    Master,
+    // A prepared board and piece sequence with a goal to reach.
+    Puzzle { goal: PuzzleGoal },
+    // Perfect clears from a series of openers, counting the streak.
+    PerfectClear,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum PuzzleGoal {
+    Lines(u32),
+    PerfectClear,
+    TSpinDouble,
+}
+
+impl PuzzleGoal {
+    pub fn description(&self) -> String {
+        match *self {
+            PuzzleGoal::Lines(1) => "CLEAR 1 LINE".to_string(),
+            PuzzleGoal::Lines(lines) => format!("CLEAR {} LINES", lines),
+            PuzzleGoal::PerfectClear => "PERFECT CLEAR".to_string(),
+            PuzzleGoal::TSpinDouble => "T-SPIN DOUBLE".to_string(),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match *self {
+            PuzzleGoal::Lines(lines) => format!("lines-{}", lines),
+            PuzzleGoal::PerfectClear => "perfect-clear".to_string(),
+            PuzzleGoal::TSpinDouble => "tsd".to_string(),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<PuzzleGoal> {
+        match name {
+            "perfect-clear" => Some(PuzzleGoal::PerfectClear),
+            "tsd" => Some(PuzzleGoal::TSpinDouble),
+            _ => name.strip_prefix("lines-")?.parse().ok().map(PuzzleGoal::Lines),
+        }
+    }
+}
+
+/// Rule changes that can be added to any mode.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Variants {
+    // Locked blocks are hidden, except for a moment after lines clear and
+    // once the game is over.
+    pub invisible: bool,
+    // Every cell of a piece covers 2x2 cells of the board.
+    pub big: bool,
+    // Pieces dealt instead of the seven tetrominoes.
+    #[cfg_attr(feature = "serde", serde(with = "crate::pieceset::serde_source"))]
+    pub piece_set: Option<&'static PieceSet>,
+}
+
+impl Variants {
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        if self.big {
+            names.push("big");
+        }
+        if self.invisible {
+            names.push("invisible");
+        }
+        names
+    }
+
+    pub fn from_names<'a, I: Iterator<Item = &'a str>>(names: I) -> Option<Variants> {
+        let mut variants = Variants::default();
+        for name in names {
+            match name {
+                "big" => variants.big = true,
+                "invisible" => variants.invisible = true,
+                _ => return None,
+            }
+        }
+        Some(variants)
+    }
+
+    /// Added to the mode's leaderboard name, so variant games are ranked
+    /// on their own.
+    pub fn suffix(&self) -> String {
+        let mut suffix = self.names().iter().map(|name| format!("+{}", name)).collect::<String>();
+        if let Some(set) = self.piece_set {
+            suffix += &format!("+{}", set.name);
+        }
+        suffix
+    }
+
+    /// The pieces the randomizer deals from.
+    pub fn piece_kinds(&self) -> &'static [PieceKind] {
+        self.piece_set.map_or(&PieceKind::ALL, |set| &set.kinds)
+    }
+
+    /// The narrowest board every piece fits across.
+    pub fn min_width(&self) -> usize {
+        self.piece_kinds().iter().map(|kind| kind.def().width()).max().unwrap_or(0) * self.scale()
+    }
+
+    /// Board cells per side of a piece cell.
+    pub fn scale(&self) -> usize {
+        if self.big { 2 } else { 1 }
+    }
+}
+
+/// How a mode's leaderboard is ordered.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Ranking {
+    Score,
+    Time,
+    Grade,
+}
+
+impl GameMode {
+    pub const NAMES: [&'static str; 10] = [
+        "marathon-150", "marathon-200", "endless", "sprint", "ultra", "ultra-2", "dig", "survival", "master", "pc",
+    ];
+
+    pub fn from_name(name: &str) -> Option<GameMode> {
+        match name {
+            "marathon-150" => Some(GameMode::Marathon { lines: Some(MARATHON_LINES[0]) }),
+            "marathon-200" => Some(GameMode::Marathon { lines: Some(MARATHON_LINES[1]) }),
+            // Endless games are named "marathon" in saves and leaderboards.
+            "endless" | "marathon" => Some(GameMode::Marathon { lines: None }),
+            "sprint" => Some(GameMode::Sprint { lines: SPRINT_LINES }),
+            "ultra" | "ultra-3" => Some(GameMode::Ultra { minutes: ULTRA_MINUTES[0] }),
+            "ultra-2" => Some(GameMode::Ultra { minutes: ULTRA_MINUTES[1] }),
+            "dig" => Some(GameMode::Dig { lines: DIG_LINES, messiness: DIG_MESSINESS }),
+            "survival" => Some(GameMode::Survival { messiness: SURVIVAL_MESSINESS }),
+            "master" => Some(GameMode::Master),
+            "pc" => Some(GameMode::PerfectClear),
+            _ => None,
+        }
+    }
+
+    /// The same mode with a different line goal, for modes that have one.
+    pub fn with_line_goal(&self, goal: u32) -> Option<GameMode> {
+        match *self {
+            GameMode::Marathon { .. } => Some(GameMode::Marathon { lines: Some(goal) }),
+            GameMode::Sprint { .. } => Some(GameMode::Sprint { lines: goal }),
+            GameMode::Dig { messiness, .. } => Some(GameMode::Dig { lines: goal, messiness }),
+            _ => None,
+        }
+    }
+
+    pub fn with_messiness(&self, messiness: u32) -> Option<GameMode> {
+        match *self {
+            GameMode::Dig { lines, .. } => Some(GameMode::Dig { lines, messiness }),
+            GameMode::Survival { .. } => Some(GameMode::Survival { messiness }),
+            _ => None,
+        }
+    }
+
+    /// The leaderboard this mode's results go to. Variants with a different
+    /// goal get their own board.
+    pub fn leaderboard(&self) -> String {
+        match *self {
+            // Endless games keep the board that predates game modes.
+            GameMode::Marathon { lines: None } => "marathon".to_string(),
+            GameMode::Marathon { lines: Some(lines) } => format!("marathon-{}", lines),
+            GameMode::Sprint { lines } => format!("sprint-{}", lines),
+            GameMode::Ultra { minutes } => format!("ultra-{}", minutes),
+            GameMode::Dig { lines, messiness } => format!("dig-{}-m{}", lines, messiness),
+            GameMode::Survival { messiness } => format!("survival-m{}", messiness),
+            GameMode::Master => "master".to_string(),
+            GameMode::Puzzle { .. } => "puzzle".to_string(),
+            GameMode::PerfectClear => "pc".to_string(),
+        }
+    }
+
+    /// A name that keeps every setting of the mode, which `parse` turns
+    /// back into the same mode.
+    pub fn name(&self) -> String {
+        match *self {
+            GameMode::Puzzle { goal } => format!("puzzle-{}", goal.name()),
+            _ => self.leaderboard(),
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<GameMode> {
+        let (kind, settings) = match name.find('-') {
+            Some(index) => (&name[..index], Some(&name[index + 1..])),
+            None => (name, None),
+        };
+        // Names come from saved games and replays, which may have been
+        // edited, so the settings are held to what the game can play.
+        let in_range = |setting: &str, min: u32, max: u32| setting.parse().ok().filter(|value| (min..=max).contains(value));
+        let lines = |setting: &str| in_range(setting, 1, MAX_LINE_GOAL);
+        let messiness = |setting: &str| in_range(setting.strip_prefix('m')?, 0, MAX_MESSINESS);
+        match (kind, settings) {
+            ("marathon", None) => Some(GameMode::Marathon { lines: None }),
+            ("marathon", Some(goal)) => Some(GameMode::Marathon { lines: Some(lines(goal)?) }),
+            ("sprint", Some(goal)) => Some(GameMode::Sprint { lines: lines(goal)? }),
+            ("ultra", Some(minutes)) => Some(GameMode::Ultra { minutes: in_range(minutes, 1, MAX_ULTRA_MINUTES)? }),
+            ("dig", Some(settings)) => {
+                let (goal, messiness_setting) = settings.split_at(settings.find('-')?);
+                Some(GameMode::Dig { lines: lines(goal)?, messiness: messiness(&messiness_setting[1..])? })
+            },
+            ("survival", Some(setting)) => Some(GameMode::Survival { messiness: messiness(setting)? }),
+            ("master", None) => Some(GameMode::Master),
+            ("pc", None) => Some(GameMode::PerfectClear),
+            ("puzzle", Some(goal)) => Some(GameMode::Puzzle { goal: PuzzleGoal::from_name(goal)? }),
+            _ => None,
+        }
+    }
+
+    pub fn title(&self) -> String {
+        match *self {
+            GameMode::Marathon { lines: None } => "ENDLESS".to_string(),
+            GameMode::Marathon { lines: Some(lines) } => format!("MARATHON {}", lines),
+            GameMode::Sprint { lines } => format!("SPRINT {}", lines),
+            GameMode::Ultra { minutes } => format!("ULTRA {} MIN", minutes),
+            GameMode::Dig { lines, .. } => format!("DIG {}", lines),
+            GameMode::Survival { .. } => "SURVIVAL".to_string(),
+            GameMode::Master => "MASTER".to_string(),
+            GameMode::Puzzle { .. } => "PUZZLE".to_string(),
+            GameMode::PerfectClear => "PC TRAINING".to_string(),
+        }
+    }
+
+    pub fn ranking(&self) -> Ranking {
+        match *self {
+            GameMode::Marathon { .. }
+            | GameMode::Ultra { .. }
+            | GameMode::Survival { .. }
+            | GameMode::Puzzle { .. }
+            | GameMode::PerfectClear => Ranking::Score,
+            GameMode::Sprint { .. } | GameMode::Dig { .. } => Ranking::Time,
+            GameMode::Master => Ranking::Grade,
+        }
+    }
+
+    pub fn line_goal(&self) -> Option<u32> {
+        match *self {
+            GameMode::Marathon { lines } => lines,
+            GameMode::Sprint { lines } => Some(lines),
+            GameMode::Master => Some(MASTER_LINES),
+            GameMode::Puzzle { goal: PuzzleGoal::Lines(lines) } => Some(lines),
+            _ => None,
+        }
+    }
+
+    /// Puzzles and perfect clear training are practice and have no
+    /// leaderboard.
+    pub fn has_leaderboard(&self) -> bool {
+        !matches!(*self, GameMode::Puzzle { .. } | GameMode::PerfectClear)
+    }
+
+    pub fn puzzle_goal(&self) -> Option<PuzzleGoal> {
+        match *self {
+            GameMode::Puzzle { goal } => Some(goal),
+            _ => None,
+        }
+    }
+
+    /// How many garbage lines have to be cleared to finish.
+    pub fn garbage_goal(&self) -> Option<u32> {
+        match *self {
+            GameMode::Dig { lines, .. } => Some(lines),
+            _ => None,
+        }
+    }
+
+    /// Gravity in 1/`G` cells per frame when the mode sets its own instead
+    /// of following the level.
+    pub fn gravity(&self) -> Option<u32> {
+        match *self {
+            GameMode::Master => Some(20 * G),
+            _ => None,
+        }
+    }
+
+    /// Frames between a piece locking and the next one appearing.
+    pub fn are(&self, lines: u32) -> u32 {
+        match *self {
+            GameMode::Master => MASTER_ARE[master_section(lines) as usize],
+            _ => 0,
+        }
+    }
+
+    /// The lock delay in frames when the mode overrides the configured one.
+    pub fn lock_delay(&self, lines: u32) -> Option<u32> {
+        match *self {
+            GameMode::Master => Some(MASTER_LOCK_DELAY[master_section(lines) as usize]),
+            _ => None,
+        }
+    }
+
+    /// How often moving or rotating a grounded piece restarts its lock
+    /// delay. Master only restarts it when the piece drops a row.
+    pub fn lock_resets(&self) -> u32 {
+        match *self {
+            GameMode::Master => 0,
+            _ => MAX_LOCK_RESETS,
+        }
+    }
+
+    /// Whether a rotation blocked by the stack may kick the piece up a row,
+    /// which pieces under 20G need to turn at all.
+    pub fn floor_kicks(&self) -> bool {
+        *self == GameMode::Master
+    }
+
+    /// Frames until the next garbage row rises, `frames` into the game.
+    pub fn garbage_interval(&self, frames: u32) -> Option<u32> {
+        match *self {
+            GameMode::Survival { .. } => {
+                let speedups = frames / (SURVIVAL_SPEEDUP_EVERY * FRAMES_PER_SECOND);
+                let interval = SURVIVAL_START_INTERVAL
+                    .saturating_sub(speedups.saturating_mul(SURVIVAL_SPEEDUP))
+                    .max(SURVIVAL_MIN_INTERVAL);
+                Some(interval * FRAMES_PER_SECOND / 1000)
+            },
+            _ => None,
+        }
+    }
+
+    /// How many frames the game lasts, if it is played against the clock.
+    pub fn time_limit(&self) -> Option<u32> {
+        match *self {
+            GameMode::Ultra { minutes } => Some(minutes * 60 * FRAMES_PER_SECOND),
+            _ => None,
+        }
+    }
+
+    /// The clock to show after `frames` frames: the time left in timed
+    /// modes, the time taken in races and survival.
+    pub fn clock(&self, frames: u32) -> Option<u32> {
+        match (self.time_limit(), *self) {
+            (Some(limit), _) => Some(frames_to_ms(limit.saturating_sub(frames))),
+            (None, GameMode::Survival { .. }) => Some(frames_to_ms(frames)),
+            (None, _) if self.ranking() == Ranking::Time => Some(frames_to_ms(frames)),
+            _ => None,
+        }
+    }
+}
+
+/// The master section, 0 to 9, a game is in after clearing `lines` lines.
+pub fn master_section(lines: u32) -> u32 {
+    (lines / MASTER_SECTION_LINES).min(MASTER_ARE.len() as u32 - 1)
+}
+
+/// The ranking used by the leaderboard called `name`.
+pub fn leaderboard_ranking(name: &str) -> Ranking {
+    if name.starts_with("sprint-") || name.starts_with("dig-") {
+        Ranking::Time
+    } else if name == "master" || name.starts_with("master+") {
+        Ranking::Grade
+    } else {
+        Ranking::Score
+    }
+}
+
+pub fn frames_to_ms(frames: u32) -> u32 {
+    (frames as u64 * 1000 / FRAMES_PER_SECOND as u64) as u32
+}
+
+/// `m:ss.mmm`
+pub fn format_time(ms: u32) -> String {
+    format!("{}:{:02}.{:03}", ms / 60_000, ms / 1000 % 60, ms % 1000)
+}
@@ -0,0 +1,430 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+
+use toml_edit::{DocumentMut, Item, Table, Value};
+
+use crate::storage;
+use crate::tetrimino::{self, PieceDef, PieceKind};
+use crate::tetris::GARBAGE;
+
+// The sets that come with the game, by the name `--pieces` takes.
+const BUILT_IN: [(&str, &str); 2] = [
+    ("pentominoes", include_str!("../pieces/pentominoes.toml")),
+    ("small", include_str!("../pieces/small.toml")),
+];
+
+// Doubled for big mode, the rows of the biggest piece still fit in 16 bits.
+const MAX_SIZE: usize = 8;
+// Saved games write each piece of the set as one base 36 digit.
+pub const MAX_PIECES: usize = 35;
+const MAX_WEIGHT: i64 = 1000;
+
+// Every set loaded so far. Loading a set again gives the same pieces, and
+// a custom piece can be traced back to its set.
+static LOADED: Mutex<Vec<&'static PieceSet>> = Mutex::new(Vec::new());
+
+/// Pieces to deal instead of the seven tetrominoes, from a file like
+///
+/// ```toml
+/// [[piece]]
+/// name = "P"
+/// color = 3             # 1 to 7, the colors of I L J O S Z T
+/// weight = 2            # optional, how often it is dealt [default: 1]
+/// kicks = [0, -1, 1]    # optional, columns a rotation tries
+/// shape = """
+/// ##
+/// ##
+/// #.
+/// """
+/// ```
+///
+/// The other rotations are the `shape` turned clockwise. A piece can also
+/// draw every rotation itself, as `rotations = ["""...""", """..."""]`.
+#[derive(PartialEq, Eq, Hash, Debug)]
+pub struct PieceSet {
+    // Added to leaderboard names, so these games are ranked on their own.
+    pub name: String,
+    // The built-in name or the path the set was loaded from, so saved
+    // games and replays can load it again.
+    pub source: String,
+    pub kinds: Vec<PieceKind>,
+}
+
+#[derive(Debug)]
+pub enum PieceSetError {
+    Io(io::Error),
+    Parse(String),
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for PieceSetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PieceSetError::Io(ref e) => write!(f, "{}", e),
+            PieceSetError::Parse(ref e) => write!(f, "{}", e.trim_end()),
+            PieceSetError::Invalid(ref errors) => {
+                write!(f, "invalid piece set:")?;
+                for error in errors {
+                    write!(f, "\n  {}", error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+type Drawing = Vec<Vec<bool>>;
+
+fn parse_drawing(drawing: &str) -> Option<Drawing> {
+    let rows = drawing.lines()
+        .map(|row| row.trim())
+        .filter(|row| !row.is_empty())
+        .map(|row| row.chars().map(|cell| match cell {
+            '#' => Some(true),
+            '.' => Some(false),
+            _ => None,
+        }).collect())
+        .collect::<Option<Vec<Vec<bool>>>>()?;
+    if rows.iter().flatten().any(|&cell| cell) { Some(rows) } else { None }
+}
+
+// Drops the empty rows and columns around the cells.
+fn trim(drawing: &Drawing) -> Drawing {
+    let filled = |y: usize, x: usize| drawing[y].get(x).cloned().unwrap_or(false);
+    let width = drawing.iter().map(Vec::len).max().unwrap_or(0);
+    let rows = (0..drawing.len()).filter(|&y| (0..width).any(|x| filled(y, x))).collect::<Vec<_>>();
+    let columns = (0..width).filter(|&x| (0..drawing.len()).any(|y| filled(y, x))).collect::<Vec<_>>();
+    (rows[0]..=rows[rows.len() - 1])
+        .map(|y| (columns[0]..=columns[columns.len() - 1]).map(|x| filled(y, x)).collect())
+        .collect()
+}
+
+fn turn_clockwise(drawing: &Drawing) -> Drawing {
+    let height = drawing.len();
+    (0..drawing[0].len())
+        .map(|x| (0..height).map(|y| drawing[height - 1 - y][x]).collect())
+        .collect()
+}
+
+fn drawing_size(drawing: &Drawing) -> usize {
+    drawing.len().max(drawing.iter().map(Vec::len).max().unwrap_or(0))
+}
+
+// `size` rows of filled columns, bit x for column x.
+fn drawing_rows(drawing: &Drawing, size: usize) -> Vec<u16> {
+    (0..size)
+        .map(|y| drawing.get(y).map_or(0, |row| {
+            row.iter().enumerate().filter(|&(_, &cell)| cell).fold(0, |bits, (x, _)| bits | 1 << x)
+        }))
+        .collect()
+}
+
+fn leak<T>(value: Vec<T>) -> &'static [T] {
+    Box::leak(value.into_boxed_slice())
+}
+
+// A piece as read from its file, only leaked into a `PieceDef` once the
+// whole set is accepted.
+struct ParsedPiece {
+    name: String,
+    color: u8,
+    size: usize,
+    spawn_offset: isize,
+    kicks: Vec<isize>,
+    weight: u32,
+    rows: Vec<u16>,
+}
+
+impl ParsedPiece {
+    fn leak(self) -> PieceKind {
+        let big_rotations = self.rows.iter().flat_map(|&row| vec![tetrimino::double_row(row); 2]).collect();
+        PieceKind::Custom(Box::leak(Box::new(PieceDef {
+            name: Box::leak(self.name.into_boxed_str()),
+            color: self.color,
+            size: self.size,
+            spawn_offset: self.spawn_offset,
+            kicks: leak(self.kicks),
+            weight: self.weight,
+            rotations: leak(self.rows),
+            big_rotations: leak(big_rotations),
+        })))
+    }
+}
+
+// A leaderboard friendly name for a set file.
+fn set_name(path: &Path) -> String {
+    let stem = path.file_stem().map_or("pieces".into(), |stem| stem.to_string_lossy());
+    stem.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' }).collect()
+}
+
+// Rotations drawn in `key`, or `None` with an error if they aren't there
+// or don't make sense.
+fn drawings(piece: &Table, key: &str, what: &str, errors: &mut Vec<String>) -> Option<Vec<Drawing>> {
+    let strings = match piece.get(key) {
+        None => return None,
+        Some(item) if key == "shape" => item.as_str().map(|shape| vec![shape]),
+        Some(item) => item.as_array().and_then(|rotations| rotations.iter().map(Value::as_str).collect()),
+    };
+    let drawings = strings
+        .filter(|strings| !strings.is_empty())
+        .and_then(|strings| strings.into_iter().map(parse_drawing).collect::<Option<Vec<_>>>());
+    if drawings.is_none() {
+        errors.push(format!("{}: {} must be drawn with '#' and '.' and have at least one '#'", what, key));
+    }
+    drawings
+}
+
+fn parse_piece(piece: &Table, what: &str, errors: &mut Vec<String>) -> Option<ParsedPiece> {
+    for (key, _) in piece.iter() {
+        if !["name", "color", "weight", "kicks", "shape", "rotations"].contains(&key) {
+            errors.push(format!("{}: {}: unknown setting", what, key));
+        }
+    }
+    let error_count = errors.len();
+
+    let name = piece.get("name").and_then(Item::as_str).unwrap_or("");
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        errors.push(format!("{}: name must be a word", what));
+    }
+    let color = match piece.get("color").and_then(Item::as_integer) {
+        Some(color) if (1..GARBAGE as i64).contains(&color) => color as u8,
+        _ => {
+            errors.push(format!("{}: color must be a whole number from 1 to {}", what, GARBAGE - 1));
+            1
+        },
+    };
+    let weight = match piece.get("weight").map(Item::as_integer) {
+        None => 1,
+        Some(Some(weight)) if (1..=MAX_WEIGHT).contains(&weight) => weight as u32,
+        Some(_) => {
+            errors.push(format!("{}: weight must be a whole number from 1 to {}", what, MAX_WEIGHT));
+            1
+        },
+    };
+    let kicks = match piece.get("kicks") {
+        None => tetrimino::KICKS.to_vec(),
+        Some(item) => {
+            let kicks = item.as_array()
+                .and_then(|kicks| kicks.iter().map(|kick| kick.as_integer().map(|kick| kick as isize)).collect::<Option<Vec<_>>>())
+                .filter(|kicks| !kicks.is_empty() && kicks.iter().all(|kick| kick.abs() <= MAX_SIZE as isize));
+            kicks.unwrap_or_else(|| {
+                errors.push(format!("{}: kicks must be a list of whole numbers from -{} to {}", what, MAX_SIZE, MAX_SIZE));
+                Vec::new()
+            })
+        },
+    };
+
+    let rotations = match (drawings(piece, "shape", what, errors), drawings(piece, "rotations", what, errors)) {
+        (Some(mut shape), None) => {
+            let mut turned = trim(&shape.remove(0));
+            let mut rotations = vec![turned.clone()];
+            for _ in 0..3 {
+                turned = turn_clockwise(&turned);
+                if !rotations.contains(&turned) {
+                    rotations.push(turned.clone());
+                }
+            }
+            rotations
+        },
+        (None, Some(rotations)) => rotations,
+        (Some(_), Some(_)) => {
+            errors.push(format!("{}: give either shape or rotations, not both", what));
+            return None;
+        },
+        (None, None) => {
+            if errors.len() == error_count {
+                errors.push(format!("{}: needs a shape or rotations", what));
+            }
+            return None;
+        },
+    };
+    let size = rotations.iter().map(drawing_size).max().unwrap_or(0);
+    if size > MAX_SIZE {
+        errors.push(format!("{}: pieces must fit in {} by {} cells", what, MAX_SIZE, MAX_SIZE));
+    }
+    if errors.len() > error_count {
+        return None;
+    }
+
+    let rows = rotations.iter().flat_map(|rotation| drawing_rows(rotation, size)).collect::<Vec<_>>();
+    let spawn_columns = rows[..size].iter().fold(0, |columns, &row| columns | row);
+    let spawn_width = 16 - spawn_columns.leading_zeros();
+    Some(ParsedPiece {
+        name: name.to_string(),
+        color,
+        size,
+        spawn_offset: -(spawn_width as isize / 2),
+        kicks,
+        weight,
+        rows,
+    })
+}
+
+impl PieceSet {
+    /// Loads one of the built-in sets, or a set file. The pieces are
+    /// leaked, as they are needed until the game is closed, and a set is
+    /// only read once.
+    pub fn load(source: &str) -> Result<&'static PieceSet, PieceSetError> {
+        let built_in = BUILT_IN.iter().find(|&&(name, _)| name == source);
+        let path = match built_in {
+            Some(_) => None,
+            // Saved games and replays may be opened from elsewhere.
+            None => Some(fs::canonicalize(source).map_err(PieceSetError::Io)?),
+        };
+        let source = path.as_ref().map_or(source.to_string(), |path| path.display().to_string());
+
+        let mut loaded = LOADED.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(&set) = loaded.iter().find(|set| set.source == source) {
+            return Ok(set);
+        }
+        let (name, content) = match (built_in, path) {
+            (Some(&(name, content)), _) => (name.to_string(), content.to_string()),
+            (None, Some(path)) => {
+                let content = storage::read_file(&path).map_err(PieceSetError::Io)?
+                    .ok_or_else(|| PieceSetError::Io(io::Error::new(io::ErrorKind::NotFound, "no such file")))?;
+                (set_name(&path), content)
+            },
+            (None, None) => unreachable!("set files have a path"),
+        };
+        let kinds = PieceSet::parse(&content)?;
+        let set = Box::leak(Box::new(PieceSet { name, source, kinds }));
+        loaded.push(set);
+        Ok(set)
+    }
+
+    pub fn is_built_in(source: &str) -> bool {
+        BUILT_IN.iter().any(|&(name, _)| name == source)
+    }
+
+    /// The loaded set `def` is a piece of, and where in it.
+    #[cfg(feature = "serde")]
+    pub fn of_piece(def: &PieceDef) -> Option<(&'static PieceSet, usize)> {
+        let loaded = LOADED.lock().unwrap_or_else(PoisonError::into_inner);
+        loaded.iter().find_map(|&set| {
+            let index = set.kinds.iter().position(|kind| std::ptr::eq(kind.def(), def))?;
+            Some((set, index))
+        })
+    }
+
+    pub fn parse(content: &str) -> Result<Vec<PieceKind>, PieceSetError> {
+        let doc = content.parse::<DocumentMut>().map_err(|e| PieceSetError::Parse(e.to_string()))?;
+        let mut errors = Vec::new();
+
+        for (key, _) in doc.iter() {
+            if key != "piece" {
+                errors.push(format!("{}: unknown setting", key));
+            }
+        }
+        let pieces = match doc.get("piece").and_then(Item::as_array_of_tables) {
+            Some(pieces) if (1..=MAX_PIECES).contains(&pieces.len()) => pieces,
+            _ => {
+                errors.push(format!("expected 1 to {} [[piece]] tables", MAX_PIECES));
+                return Err(PieceSetError::Invalid(errors));
+            },
+        };
+
+        let mut names = HashSet::new();
+        let mut parsed = Vec::new();
+        for (index, piece) in pieces.iter().enumerate() {
+            let what = format!("piece {}", index + 1);
+            if let Some(piece) = parse_piece(piece, &what, &mut errors) {
+                if !names.insert(piece.name.clone()) {
+                    errors.push(format!("{}: there is already a piece named {}", what, piece.name));
+                }
+                parsed.push(piece);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(parsed.into_iter().map(ParsedPiece::leak).collect())
+        } else {
+            Err(PieceSetError::Invalid(errors))
+        }
+    }
+}
+
+// Serde can't store a leaked piece, so a custom piece is written as its
+// set's source and its place in the set, and loaded again when read, as
+// saved games do.
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "PieceKind")]
+enum SerdePieceKind {
+    I,
+    L,
+    J,
+    O,
+    S,
+    Z,
+    T,
+    Custom { set: String, index: usize },
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for PieceKind {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let kind = match *self {
+            PieceKind::I => SerdePieceKind::I,
+            PieceKind::L => SerdePieceKind::L,
+            PieceKind::J => SerdePieceKind::J,
+            PieceKind::O => SerdePieceKind::O,
+            PieceKind::S => SerdePieceKind::S,
+            PieceKind::Z => SerdePieceKind::Z,
+            PieceKind::T => SerdePieceKind::T,
+            PieceKind::Custom(def) => {
+                let (set, index) = PieceSet::of_piece(def)
+                    .ok_or_else(|| serde::ser::Error::custom(format!("piece {} is not from a loaded set", def.name)))?;
+                SerdePieceKind::Custom { set: set.source.clone(), index }
+            },
+        };
+        kind.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for PieceKind {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<PieceKind, D::Error> {
+        use serde::de::Error;
+
+        Ok(match SerdePieceKind::deserialize(deserializer)? {
+            SerdePieceKind::I => PieceKind::I,
+            SerdePieceKind::L => PieceKind::L,
+            SerdePieceKind::J => PieceKind::J,
+            SerdePieceKind::O => PieceKind::O,
+            SerdePieceKind::S => PieceKind::S,
+            SerdePieceKind::Z => PieceKind::Z,
+            SerdePieceKind::T => PieceKind::T,
+            SerdePieceKind::Custom { set, index } => {
+                let piece_set = PieceSet::load(&set).map_err(|e| D::Error::custom(format!("piece set {}: {}", set, e)))?;
+                *piece_set.kinds.get(index)
+                    .ok_or_else(|| D::Error::custom(format!("piece set {} has no piece {}", set, index)))?
+            },
+        })
+    }
+}
+
+/// `Variants::piece_set` as the set's source, for serde.
+#[cfg(feature = "serde")]
+pub mod serde_source {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::PieceSet;
+
+    pub fn serialize<S: Serializer>(set: &Option<&'static PieceSet>, serializer: S) -> Result<S::Ok, S::Error> {
+        set.map(|set| &set.source).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<&'static PieceSet>, D::Error> {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(source) => PieceSet::load(&source)
+                .map(Some)
+                .map_err(|e| D::Error::custom(format!("piece set {}: {}", source, e))),
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use toml_edit::DocumentMut;
+
+use crate::mode::PuzzleGoal;
+use crate::storage;
+use crate::tetrimino::PieceKind;
+use crate::tetris::{self, GARBAGE};
+
+/// A prepared board, the pieces to play on it and what to achieve.
+///
+/// ```toml
+/// goal = "tsd"            # "lines", "perfect-clear" or "tsd"
+/// lines = 2               # with goal = "lines"
+/// pieces = "TIO"
+/// height = 16             # optional, the board rows sit at the bottom
+/// board = """
+/// ZZ.....III
+/// #.########
+/// """
+/// ```
+///
+/// Board cells are `.` for empty, a piece letter for a block of that color
+/// or `#` for garbage.
+pub struct Puzzle {
+    pub goal: PuzzleGoal,
+    pub board: Vec<Vec<u8>>,
+    pub pieces: Vec<PieceKind>,
+}
+
+#[derive(Debug)]
+pub enum PuzzleError {
+    Io(io::Error),
+    Parse(String),
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for PuzzleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PuzzleError::Io(ref e) => write!(f, "{}", e),
+            PuzzleError::Parse(ref e) => write!(f, "{}", e.trim_end()),
+            PuzzleError::Invalid(ref errors) => {
+                write!(f, "invalid puzzle:")?;
+                for error in errors {
+                    write!(f, "\n  {}", error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A perfect clear setup built from the first bag, with the piece it
+/// leaves over held for the finish.
+pub struct PcOpener {
+    pub name: &'static str,
+    pub hold: PieceKind,
+    rows: [&'static str; 4],
+}
+
+// Openers for perfect clear practice. Both leave the T in hold and a
+// 4-line perfect clear on a 10 wide board to finish with the next bag.
+const PC_OPENERS: [PcOpener; 2] = [
+    PcOpener {
+        name: "PCO",
+        hold: PieceKind::T,
+        rows: [
+            "LLLS......",
+            "LZZSS....J",
+            "OOZZS....J",
+            "OOIIII..JJ",
+        ],
+    },
+    PcOpener {
+        name: "PCO (mirrored)",
+        hold: PieceKind::T,
+        rows: [
+            "......ZJJJ",
+            "L....ZZSSJ",
+            "L....ZSSOO",
+            "LL..IIIIOO",
+        ],
+    },
+];
+
+pub const PC_OPENER_WIDTH: usize = 10;
+
+fn piece(letter: char) -> Option<PieceKind> {
+    PieceKind::from_name(letter.encode_utf8(&mut [0; 4]))
+}
+
+fn parse_row(row: &str) -> Option<Vec<u8>> {
+    row.chars().map(|cell| match cell {
+        '.' => Some(0),
+        '#' => Some(GARBAGE),
+        letter => piece(letter).map(PieceKind::color),
+    }).collect()
+}
+
+/// Opener `index`, wrapping around.
+pub fn pc_opener(index: usize) -> &'static PcOpener {
+    &PC_OPENERS[index % PC_OPENERS.len()]
+}
+
+impl PcOpener {
+    /// The opener at the bottom of an empty board.
+    pub fn board(&self, height: usize) -> Vec<Vec<u8>> {
+        let mut board = vec![vec![0; PC_OPENER_WIDTH]; height - self.rows.len()];
+        board.extend(self.rows.iter().map(|row| parse_row(row).expect("openers use valid cells")));
+        board
+    }
+}
+
+impl Puzzle {
+    pub fn load(path: &Path) -> Result<Puzzle, PuzzleError> {
+        match storage::read_file(path).map_err(PuzzleError::Io)? {
+            Some(content) => Puzzle::parse(&content),
+            None => Err(PuzzleError::Io(io::Error::new(io::ErrorKind::NotFound, "no such file"))),
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<Puzzle, PuzzleError> {
+        let doc = content.parse::<DocumentMut>().map_err(|e| PuzzleError::Parse(e.to_string()))?;
+        let mut errors = Vec::new();
+
+        for (key, _) in doc.iter() {
+            if !["goal", "lines", "pieces", "height", "board"].contains(&key) {
+                errors.push(format!("{}: unknown setting", key));
+            }
+        }
+
+        let lines = doc.get("lines").map(|item| match item.as_integer() {
+            Some(n) if n >= 1 && n <= 4 * tetris::MAX_HEIGHT as i64 => n as u32,
+            _ => {
+                errors.push(format!("lines: expected a positive whole number, found {}", item.to_string().trim()));
+                1
+            }
+        });
+        let goal = match doc.get("goal").map(|item| item.as_str()) {
+            Some(Some("lines")) => PuzzleGoal::Lines(lines.unwrap_or(1)),
+            Some(Some("perfect-clear")) => PuzzleGoal::PerfectClear,
+            Some(Some("tsd")) => PuzzleGoal::TSpinDouble,
+            _ => {
+                errors.push("goal: expected \"lines\", \"perfect-clear\" or \"tsd\"".to_string());
+                PuzzleGoal::Lines(1)
+            }
+        };
+
+        let pieces = match doc.get("pieces").and_then(|item| item.as_str()) {
+            Some(letters) => letters.chars()
+                .filter(|c| !c.is_whitespace())
+                .filter_map(|letter| {
+                    let piece = piece(letter);
+                    if piece.is_none() {
+                        let names = PieceKind::ALL.iter().map(|kind| kind.name()).collect::<String>();
+                        errors.push(format!("pieces: '{}' is not one of {}", letter, names));
+                    }
+                    piece
+                })
+                .collect::<Vec<_>>(),
+            None => Vec::new(),
+        };
+        if pieces.is_empty() {
+            errors.push("pieces: expected a string of piece letters like \"TIO\"".to_string());
+        }
+
+        let rows = doc.get("board").and_then(|item| item.as_str()).unwrap_or("")
+            .lines()
+            .map(|row| row.trim())
+            .filter(|row| !row.is_empty())
+            .map(parse_row)
+            .collect::<Option<Vec<_>>>();
+
+        let mut board = Vec::new();
+        match rows {
+            None => errors.push("board: cells must be '.', '#' or a piece letter".to_string()),
+            Some(ref rows) if rows.is_empty() => errors.push("board: expected at least one row".to_string()),
+            Some(rows) => {
+                let width = rows[0].len();
+                let height = match doc.get("height") {
+                    Some(item) => match item.as_integer() {
+                        Some(n) if n >= tetris::MIN_HEIGHT as i64 && n <= tetris::MAX_HEIGHT as i64 => n as usize,
+                        _ => {
+                            errors.push(format!(
+                                "height: expected a whole number between {} and {}",
+                                tetris::MIN_HEIGHT, tetris::MAX_HEIGHT
+                            ));
+                            tetris::DEFAULT_HEIGHT
+                        }
+                    },
+                    None => tetris::DEFAULT_HEIGHT,
+                };
+
+                if rows.iter().any(|row| row.len() != width) {
+                    errors.push("board: all rows must be the same width".to_string());
+                } else if !(tetris::MIN_WIDTH..=tetris::MAX_WIDTH).contains(&width) {
+                    errors.push(format!(
+                        "board: rows must be {} to {} cells wide", tetris::MIN_WIDTH, tetris::MAX_WIDTH
+                    ));
+                } else if rows.len() > height {
+                    errors.push(format!("board: more than {} rows", height));
+                } else {
+                    board = vec![vec![0; width]; height - rows.len()];
+                    board.extend(rows);
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(Puzzle { goal, board, pieces })
+        } else {
+            Err(PuzzleError::Invalid(errors))
+        }
+    }
+}
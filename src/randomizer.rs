@@ -0,0 +1,92 @@
+use rand::{Rng, RngCore};
+use rand::rand_core::impls;
+
+use crate::tetrimino::PieceKind;
+
+/// Seeded piece randomizer: the same seed always deals the same pieces.
+/// A piece equal to the previous one is rerolled once, which keeps the feel
+/// of the original `rand::random` based dealer. Pieces are dealt as often
+/// as their weight says, compared to the rest of the set.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Randomizer {
+    state: u64,
+    // Where the previous piece is in the set.
+    previous: Option<usize>,
+}
+
+impl Randomizer {
+    pub fn new(seed: u64) -> Randomizer {
+        Randomizer {
+            state: seed,
+            previous: None,
+        }
+    }
+
+    /// The state as values for a saved game.
+    pub fn to_fields(&self) -> Vec<String> {
+        let previous = self.previous.map_or("-".to_string(), |index| index.to_string());
+        vec![self.state.to_string(), previous]
+    }
+
+    pub fn from_fields(values: &[&str]) -> Option<Randomizer> {
+        match *values {
+            [state, previous] => Some(Randomizer {
+                state: state.parse().ok()?,
+                previous: match previous {
+                    "-" => None,
+                    previous => Some(previous.parse().ok()?),
+                },
+            }),
+            _ => None,
+        }
+    }
+
+    // A piece of `set` picked by weight.
+    fn roll(&mut self, set: &[PieceKind]) -> usize {
+        let total = set.iter().map(|kind| kind.def().weight).sum::<u32>();
+        // The standard set keeps drawing the small numbers it always has,
+        // so old replays deal the same pieces.
+        let mut roll = if total <= u8::MAX as u32 {
+            self.random_range(0..total as u8) as u32
+        } else {
+            self.random_range(0..total)
+        };
+        for (index, kind) in set.iter().enumerate() {
+            let weight = kind.def().weight;
+            if roll < weight {
+                return index;
+            }
+            roll -= weight;
+        }
+        unreachable!("the roll is below the total weight")
+    }
+
+    pub fn next_piece(&mut self, set: &[PieceKind]) -> PieceKind {
+        let mut index = self.roll(set);
+        if Some(index) == self.previous {
+            index = self.roll(set);
+        }
+        self.previous = Some(index);
+        set[index]
+    }
+}
+
+// SplitMix64, small and with a state that is trivial to store.
+impl RngCore for Randomizer {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn fill_bytes(&mut self, dst: &mut [u8]) {
+        impls::fill_bytes_via_next(self, dst)
+    }
+}
@@ -0,0 +1,623 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use crate::config::{self, Handling};
+use crate::input::{Action, Controller};
+use crate::mode::{GameMode, Variants};
+use crate::pieceset::PieceSet;
+use crate::storage;
+use crate::tetrimino::PieceKind;
+use crate::tetris::{self, GameState, ScoringProfile, Tetris};
+
+const HEADER: &str = "tetris-replay 1";
+
+/// Something that happened in a game at a given frame, before that frame
+/// was played.
+#[derive(Clone)]
+pub enum ReplayEvent {
+    Press(Action),
+    Release(Action),
+    // The settings menu changed the handling.
+    Handling(Handling),
+    // A finished game was continued as an endless one.
+    Continue,
+}
+
+/// How the game ended, to check a playback against.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ReplayResult {
+    pub score: u32,
+    pub lines: u32,
+    pub level: u32,
+    pub frames: u32,
+    pub pieces: u32,
+    pub board_hash: u64,
+}
+
+impl ReplayResult {
+    pub fn of(tetris: &Tetris) -> ReplayResult {
+        ReplayResult {
+            score: tetris.score,
+            lines: tetris.number_of_lines,
+            level: tetris.current_level,
+            frames: tetris.frames,
+            pieces: tetris.pieces,
+            board_hash: tetris.board_hash(),
+        }
+    }
+}
+
+/// Everything needed to play a game again: how it was set up and every
+/// input in the order it was applied.
+///
+/// ```text
+/// tetris-replay 1
+/// mode sprint-40
+/// variants big
+/// piece-set pentominoes
+/// seed 1234
+/// size 10 16
+/// level 1
+/// scoring classic
+/// handling 167 33 500 50
+/// result 52 40 3 5843 104 9a3f61c2e07d4b85
+/// events
+/// 0 +left
+/// 6 -left
+/// 2100 handling 100 0 500 0
+/// ```
+///
+/// Puzzles also keep their board as `board` rows of color numbers and the
+/// piece sequence as `pieces`.
+#[derive(Clone)]
+pub struct Replay {
+    pub mode: GameMode,
+    pub variants: Variants,
+    pub seed: u64,
+    pub width: usize,
+    pub height: usize,
+    pub level: u32,
+    pub scoring: ScoringProfile,
+    pub handling: Handling,
+    // The prepared board and pieces of a puzzle.
+    pub setup: Option<(Vec<Vec<u8>>, Vec<PieceKind>)>,
+    pub result: Option<ReplayResult>,
+    pub events: Vec<(u32, ReplayEvent)>,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    Parse { line: usize, reason: String },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReplayError::Io(ref e) => write!(f, "{}", e),
+            ReplayError::Parse { line, ref reason } => write!(f, "corrupt replay, line {}: {}", line, reason),
+        }
+    }
+}
+
+fn action_from_name(name: &str) -> Option<Action> {
+    Action::ALL.iter().cloned().find(|action| action.name() == name)
+}
+
+// Held to the ranges the configuration file allows.
+fn parse_handling(values: &[&str]) -> Option<Handling> {
+    if values.len() != 4 {
+        return None;
+    }
+    let value = |index: usize, max: u32| values[index].parse().ok().filter(|&value| value <= max);
+    Some(Handling {
+        das: value(0, config::MAX_DAS)?,
+        arr: value(1, config::MAX_ARR)?,
+        lock_delay: value(2, config::MAX_LOCK_DELAY)?,
+        soft_drop: value(3, config::MAX_SOFT_DROP)?,
+    })
+}
+
+fn format_handling(handling: &Handling) -> String {
+    format!("{} {} {} {}", handling.das, handling.arr, handling.lock_delay, handling.soft_drop)
+}
+
+impl Replay {
+    /// Starts recording `tetris`, which must not have been played yet.
+    pub fn record(tetris: &Tetris, seed: u64, handling: &Handling) -> Replay {
+        let setup = match tetris.mode {
+            GameMode::Puzzle { .. } => Some((tetris.game_map.to_rows(), tetris.next_pieces.iter().cloned().collect())),
+            _ => None,
+        };
+        Replay {
+            mode: tetris.mode,
+            variants: tetris.variants,
+            seed,
+            width: tetris.width,
+            height: tetris.height,
+            level: tetris.current_level,
+            scoring: tetris.scoring,
+            handling: handling.clone(),
+            setup,
+            result: None,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, frame: u32, event: ReplayEvent) {
+        self.events.push((frame, event));
+    }
+
+    /// The game and controller as they were when the recording started.
+    pub fn start(&self) -> (Tetris, Controller) {
+        let mut tetris = match self.setup {
+            Some((ref board, ref pieces)) => Tetris::with_setup(self.mode, self.variants, board.clone(), pieces),
+            None => Tetris::new(self.mode, self.variants, self.width, self.height, self.seed),
+        };
+        tetris.current_level = self.level;
+        tetris.scoring = self.scoring;
+        tetris.lock_delay = tetris::ms_to_frames(self.handling.lock_delay);
+        (tetris, Controller::new(&self.handling))
+    }
+
+    pub fn load(path: &Path) -> Result<Replay, ReplayError> {
+        Replay::parse(&read_replay(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        storage::write_atomic(path, &self.to_string())
+    }
+
+    pub fn parse(content: &str) -> Result<Replay, ReplayError> {
+        Replay::parse_with(content, true)
+    }
+
+    // With `set_files` false only the built-in piece sets are accepted, and
+    // no other file is read.
+    fn parse_with(content: &str, set_files: bool) -> Result<Replay, ReplayError> {
+        let mut lines = content.lines().enumerate();
+        let error = |line: usize, reason: &str| ReplayError::Parse { line: line + 1, reason: reason.to_string() };
+
+        match lines.next() {
+            Some((_, HEADER)) => {},
+            _ => return Err(error(0, "not a replay file")),
+        }
+
+        let mut mode = None;
+        let mut variants = Variants::default();
+        let mut piece_set = None;
+        let mut seed = None;
+        let mut size = None;
+        let mut level = 1;
+        let mut scoring = ScoringProfile::Classic;
+        let mut handling = None;
+        let mut board = Vec::new();
+        let mut pieces = None;
+        let mut result = None;
+
+        for (line_nb, line) in lines.by_ref() {
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            let Some((&key, values)) = words.split_first() else {
+                continue;
+            };
+            let invalid = || error(line_nb, &format!("invalid {}", key));
+            let number = |value: &str| value.parse::<u32>().map_err(|_| invalid());
+            match (key, values) {
+                ("events", []) => break,
+                ("mode", [name]) => mode = Some(GameMode::parse(name).ok_or_else(invalid)?),
+                ("variants", names) => variants = Variants::from_names(names.iter().cloned()).ok_or_else(invalid)?,
+                ("piece-set", source) if !source.is_empty() => {
+                    // A path may have had spaces in it.
+                    let source = source.join(" ");
+                    if !set_files && !PieceSet::is_built_in(&source) {
+                        return Err(error(line_nb, &format!("piece set {}: only built-in sets can be verified", source)));
+                    }
+                    let set = PieceSet::load(&source)
+                        .map_err(|e| error(line_nb, &format!("piece set {}: {}", source, e)))?;
+                    piece_set = Some(set);
+                },
+                ("seed", [value]) => seed = Some(value.parse::<u64>().map_err(|_| invalid())?),
+                ("size", [width, height]) => {
+                    let (width, height) = (number(width)? as usize, number(height)? as usize);
+                    if !(tetris::MIN_WIDTH..=tetris::MAX_WIDTH).contains(&width)
+                        || !(tetris::MIN_HEIGHT..=tetris::MAX_HEIGHT).contains(&height)
+                    {
+                        return Err(invalid());
+                    }
+                    size = Some((width, height));
+                },
+                ("level", [value]) => {
+                    level = number(value)?;
+                    if !(1..=tetris::LEVEL_TIMES.len() as u32).contains(&level) {
+                        return Err(invalid());
+                    }
+                },
+                ("scoring", [name]) => scoring = ScoringProfile::from_name(name).ok_or_else(invalid)?,
+                ("handling", values) => handling = Some(parse_handling(values).ok_or_else(invalid)?),
+                ("board", [row]) => {
+                    let row = row.chars().map(|case| case.to_digit(10).map(|case| case as u8)).collect::<Option<Vec<_>>>();
+                    board.push(row.ok_or_else(invalid)?);
+                },
+                ("pieces", [sequence]) => {
+                    let sequence = sequence.chars().map(|piece| piece.to_digit(10).and_then(|piece| PieceKind::from_color(piece as u8)));
+                    pieces = Some(sequence.collect::<Option<Vec<_>>>().ok_or_else(invalid)?);
+                },
+                ("result", [score, lines, level, frames, pieces, board_hash]) => result = Some(ReplayResult {
+                    score: number(score)?,
+                    lines: number(lines)?,
+                    level: number(level)?,
+                    frames: number(frames)?,
+                    pieces: number(pieces)?,
+                    board_hash: u64::from_str_radix(board_hash, 16).map_err(|_| invalid())?,
+                }),
+                _ => return Err(error(line_nb, &format!("unexpected '{}'", line))),
+            }
+        }
+
+        let mut events = Vec::new();
+        for (line_nb, line) in lines {
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            let Some((frame, event)) = words.split_first() else {
+                continue;
+            };
+            let invalid = || error(line_nb, &format!("invalid event '{}'", line));
+            let frame = frame.parse::<u32>().map_err(|_| invalid())?;
+            let event = match event {
+                ["continue"] => ReplayEvent::Continue,
+                ["handling", values @ ..] => ReplayEvent::Handling(parse_handling(values).ok_or_else(invalid)?),
+                [action] if action.starts_with('+') => ReplayEvent::Press(action_from_name(&action[1..]).ok_or_else(invalid)?),
+                [action] if action.starts_with('-') => ReplayEvent::Release(action_from_name(&action[1..]).ok_or_else(invalid)?),
+                _ => return Err(invalid()),
+            };
+            events.push((frame, event));
+        }
+
+        variants.piece_set = piece_set;
+        let missing = |what: &str| error(0, &format!("missing {}", what));
+        let mode = mode.ok_or_else(|| missing("mode"))?;
+        let (width, height) = size.ok_or_else(|| missing("size"))?;
+        let setup = match pieces {
+            Some(pieces) if board.len() == height && board.iter().all(|row| row.len() == width) => Some((board, pieces)),
+            Some(_) => return Err(missing("board rows")),
+            None => None,
+        };
+        Ok(Replay {
+            mode,
+            variants,
+            seed: seed.ok_or_else(|| missing("seed"))?,
+            width,
+            height,
+            level,
+            scoring,
+            handling: handling.ok_or_else(|| missing("handling"))?,
+            setup,
+            result,
+            events,
+        })
+    }
+}
+
+
+impl fmt::Display for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "mode {}", self.mode.name())?;
+        let variants = self.variants.names();
+        if !variants.is_empty() {
+            writeln!(f, "variants {}", variants.join(" "))?;
+        }
+        if let Some(piece_set) = self.variants.piece_set {
+            writeln!(f, "piece-set {}", piece_set.source)?;
+        }
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "size {} {}", self.width, self.height)?;
+        writeln!(f, "level {}", self.level)?;
+        writeln!(f, "scoring {}", self.scoring.name())?;
+        writeln!(f, "handling {}", format_handling(&self.handling))?;
+        if let Some((ref board, ref pieces)) = self.setup {
+            for row in board {
+                writeln!(f, "board {}", row.iter().map(|case| case.to_string()).collect::<String>())?;
+            }
+            writeln!(f, "pieces {}", pieces.iter().map(|piece| piece.color().to_string()).collect::<String>())?;
+        }
+        if let Some(result) = self.result {
+            writeln!(
+                f, "result {} {} {} {} {} {:016x}",
+                result.score, result.lines, result.level, result.frames, result.pieces, result.board_hash
+            )?;
+        }
+        writeln!(f, "events")?;
+        for &(frame, ref event) in &self.events {
+            match *event {
+                ReplayEvent::Press(action) => writeln!(f, "{} +{}", frame, action.name())?,
+                ReplayEvent::Release(action) => writeln!(f, "{} -{}", frame, action.name())?,
+                ReplayEvent::Handling(ref handling) => writeln!(f, "{} handling {}", frame, format_handling(handling))?,
+                ReplayEvent::Continue => writeln!(f, "{} continue", frame)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// How often playback keeps a copy of the game to seek from.
+const SNAPSHOT_FRAMES: u32 = 10 * tetris::FRAMES_PER_SECOND;
+
+#[derive(Clone)]
+struct Snapshot {
+    tetris: Tetris,
+    controller: Controller,
+    next_event: usize,
+}
+
+/// Plays a replay back through the game rules, one frame at a time.
+pub struct Playback {
+    pub replay: Replay,
+    pub tetris: Tetris,
+    controller: Controller,
+    next_event: usize,
+    // One every `SNAPSHOT_FRAMES` frames, up to the furthest frame played.
+    snapshots: Vec<Snapshot>,
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Playback {
+        let (tetris, controller) = replay.start();
+        Playback { replay, tetris, controller, next_event: 0, snapshots: Vec::new() }
+    }
+
+    // The events recorded before the current frame was played.
+    fn has_due_event(&self) -> bool {
+        match self.replay.events.get(self.next_event) {
+            Some(&(frame, _)) => frame <= self.tetris.frames,
+            None => false,
+        }
+    }
+
+    /// Whether the recording has been played to its end: the game is over
+    /// and nothing continues it, or the player quit at this frame.
+    pub fn is_over(&self) -> bool {
+        if self.has_due_event() {
+            return false;
+        }
+        let quit = match self.replay.result {
+            Some(result) => self.next_event == self.replay.events.len() && self.tetris.frames >= result.frames,
+            None => false,
+        };
+        self.tetris.state != GameState::Playing || quit
+    }
+
+    /// Plays one frame the way the game loop did.
+    pub fn step(&mut self) {
+        if self.is_over() {
+            return;
+        }
+        if self.tetris.frames.is_multiple_of(SNAPSHOT_FRAMES)
+            && self.snapshots.len() as u32 == self.tetris.frames / SNAPSHOT_FRAMES
+        {
+            self.snapshots.push(Snapshot {
+                tetris: self.tetris.clone(),
+                controller: self.controller.clone(),
+                next_event: self.next_event,
+            });
+        }
+
+        while self.has_due_event() {
+            match self.replay.events[self.next_event].1 {
+                ReplayEvent::Press(action) => self.controller.press(action, &mut self.tetris),
+                ReplayEvent::Release(action) => self.controller.release(action),
+                ReplayEvent::Handling(ref handling) => {
+                    self.controller.release_all();
+                    self.controller.set_handling(handling);
+                    self.tetris.lock_delay = tetris::ms_to_frames(handling.lock_delay);
+                },
+                ReplayEvent::Continue => {
+                    self.tetris.continue_endless();
+                    self.controller.release_all();
+                },
+            }
+            self.next_event += 1;
+        }
+        if self.tetris.state == GameState::Playing {
+            self.controller.update(&mut self.tetris);
+            self.tetris.step();
+        }
+    }
+
+    /// Moves to `frame`, replaying from the closest snapshot before it
+    /// when going back.
+    pub fn seek(&mut self, frame: u32) {
+        if frame < self.tetris.frames || frame >= self.tetris.frames + SNAPSHOT_FRAMES {
+            let index = (frame / SNAPSHOT_FRAMES) as usize;
+            if let Some(snapshot) = self.snapshots.get(index.min(self.snapshots.len().saturating_sub(1))) {
+                if snapshot.tetris.frames > self.tetris.frames || frame < self.tetris.frames {
+                    self.tetris = snapshot.tetris.clone();
+                    self.controller = snapshot.controller.clone();
+                    self.next_event = snapshot.next_event;
+                }
+            }
+        }
+        while self.tetris.frames < frame && !self.is_over() {
+            self.step();
+        }
+    }
+
+    /// Whether the game played back ended with the recorded result, once
+    /// it is over. `None` for replays saved without one.
+    pub fn matches_result(&self) -> Option<bool> {
+        self.replay.result.map(|result| result == ReplayResult::of(&self.tetris))
+    }
+}
+
+/// Plays the replay at `path` without a window and prints how it ends.
+/// Returns the exit status: 0 if it matches the recorded result, 1 on a
+/// desync and 2 if it can't be read or has no result to compare with.
+fn read_replay(path: &Path) -> Result<String, ReplayError> {
+    storage::read_file(path).map_err(ReplayError::Io)?
+        .ok_or_else(|| ReplayError::Io(io::Error::new(io::ErrorKind::NotFound, "no such replay")))
+}
+
+/// Plays `path` back without a window and checks it ends with the recorded
+/// result. Replays may come from anyone, so their piece sets must be built
+/// in: a set file could hold anything, and be different on every machine.
+pub fn verify(path: &Path) -> i32 {
+    let replay = match read_replay(path).and_then(|content| Replay::parse_with(&content, false)) {
+        Ok(replay) => replay,
+        Err(e) => {
+            eprintln!("tetris: {}: {}", path.display(), e);
+            return 2;
+        },
+    };
+    // Nothing to audit a score against.
+    let Some(recorded) = replay.result else {
+        eprintln!("tetris: {}: no recorded result", path.display());
+        return 2;
+    };
+    let mut playback = Playback::new(replay);
+    while !playback.is_over() {
+        playback.step();
+    }
+
+    let played = ReplayResult::of(&playback.tetris);
+    println!("mode    {}", playback.replay.mode.name());
+    println!("score   {}", played.score);
+    println!("lines   {}", played.lines);
+    println!("level   {}", played.level);
+    println!("pieces  {}", played.pieces);
+    println!("frames  {}", played.frames);
+    println!("board   {:016x}", played.board_hash);
+    if recorded == played {
+        println!("result  ok");
+        0
+    } else {
+        println!("result  desync");
+        eprintln!(
+            "tetris: {}: recorded score {}, lines {}, level {}, pieces {}, frames {}, board {:016x}",
+            path.display(), recorded.score, recorded.lines, recorded.level,
+            recorded.pieces, recorded.frames, recorded.board_hash
+        );
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    const REPLAY: &str = "\
+tetris-replay 1
+mode sprint-40
+seed 1234
+size 10 16
+level 1
+scoring classic
+handling 167 33 500 50
+events
+0 +left
+6 -left
+";
+
+    // `REPLAY` with the line for `key` replaced by `line`.
+    fn with_line(key: &str, line: &str) -> String {
+        REPLAY.lines()
+            .map(|old| if old.split_whitespace().next() == Some(key) { line } else { old })
+            .map(|line| format!("{}\n", line))
+            .collect()
+    }
+
+    fn rejected_line(key: &str, line: &str) -> usize {
+        match Replay::parse(&with_line(key, line)) {
+            Err(ReplayError::Parse { line, .. }) => line,
+            Err(e) => panic!("{}: {}", line, e),
+            Ok(_) => panic!("{}: accepted", line),
+        }
+    }
+
+    #[test]
+    fn parses_a_replay() {
+        let replay = Replay::parse(REPLAY).unwrap();
+        assert_eq!(replay.mode, GameMode::Sprint { lines: 40 });
+        assert_eq!((replay.width, replay.height, replay.level), (10, 16, 1));
+        assert_eq!(replay.events.len(), 2);
+        assert_eq!(Replay::parse(&replay.to_string()).unwrap().to_string(), replay.to_string());
+    }
+
+    #[test]
+    fn rejects_boards_the_game_cant_have() {
+        for size in ["size 20 16", "size 0 0", "size 3 16", "size 10 3", "size 10 41"] {
+            assert_eq!(rejected_line("size", size), 4, "{}", size);
+        }
+    }
+
+    #[test]
+    fn rejects_levels_the_game_cant_start_at() {
+        for level in ["level 0", "level 11", "level -1"] {
+            assert_eq!(rejected_line("level", level), 5, "{}", level);
+        }
+    }
+
+    #[test]
+    fn rejects_mode_settings_out_of_range() {
+        let modes = [
+            "mode sprint-0", "mode sprint-1000", "mode marathon-0", "mode ultra-0", "mode ultra-99999999",
+            "mode dig-0-m30", "mode dig-1000-m30", "mode dig-10-m101", "mode survival-m101",
+        ];
+        for mode in modes {
+            assert_eq!(rejected_line("mode", mode), 2, "{}", mode);
+        }
+    }
+
+    #[test]
+    fn rejects_handling_out_of_range() {
+        for handling in ["handling 1001 33 500 50", "handling 167 501 500 50", "handling 167 33 5001 50", "handling 167 33 500 1001"] {
+            assert_eq!(rejected_line("handling", handling), 7, "{}", handling);
+        }
+    }
+
+    // Saves `replay` where `verify` can read it and returns its exit status.
+    fn verify_status(replay: &Replay, name: &str) -> i32 {
+        let path = env::temp_dir().join(format!("tetris-test-{}-{}.replay", process::id(), name));
+        fs::write(&path, replay.to_string()).unwrap();
+        let status = verify(&path);
+        fs::remove_file(&path).unwrap();
+        status
+    }
+
+    #[test]
+    fn verify_compares_the_recorded_result() {
+        let mut replay = Replay::parse(&with_line("mode", "mode marathon")).unwrap();
+        let mut playback = Playback::new(replay.clone());
+        while !playback.is_over() {
+            playback.step();
+        }
+        let result = ReplayResult::of(&playback.tetris);
+
+        assert_eq!(verify_status(&replay, "none"), 2);
+        replay.result = Some(result);
+        assert_eq!(verify_status(&replay, "ok"), 0);
+        replay.result = Some(ReplayResult { score: result.score + 1, ..result });
+        assert_eq!(verify_status(&replay, "desync"), 1);
+    }
+
+    #[test]
+    fn verify_only_loads_built_in_piece_sets() {
+        let set_file = env::temp_dir().join(format!("tetris-test-{}.toml", process::id()));
+        fs::write(&set_file, "[[piece]]\nname = \"A\"\ncolor = 1\nshape = \"##\"\n").unwrap();
+        let content = REPLAY.replace("seed", &format!("piece-set {}\nseed", set_file.display()));
+        let mut replay = Replay::parse(&content).unwrap();
+        replay.result = Some(ReplayResult::of(&replay.start().0));
+        assert_eq!(verify_status(&replay, "set-file"), 2);
+        fs::remove_file(&set_file).unwrap();
+
+        let mut replay = Replay::parse(&REPLAY.replace("seed", "piece-set small\nseed")).unwrap();
+        let mut playback = Playback::new(replay.clone());
+        while !playback.is_over() {
+            playback.step();
+        }
+        replay.result = Some(ReplayResult::of(&playback.tetris));
+        assert_eq!(verify_status(&replay, "built-in"), 0);
+    }
+}
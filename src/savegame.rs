@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use crate::replay::{Replay, ReplayError};
+use crate::storage;
+use crate::tetris::Tetris;
+
+const HEADER: &str = "tetris-save 2";
+// Separates the game from its replay, which follows in its own format.
+const REPLAY_MARKER: &str = "replay";
+
+/// A game put aside to be finished later, with the replay recorded so far
+/// so the finished game still has one.
+///
+/// ```text
+/// tetris-save 2
+/// replay-name 1700000000000-marathon-150.replay
+/// mode marathon-150
+/// score 1200
+/// board 0000000000 ... 7700000000
+/// ...
+/// replay
+/// tetris-replay 1
+/// ...
+/// ```
+pub struct SaveGame {
+    pub tetris: Tetris,
+    pub replay: Replay,
+    // The file the replay will be saved as, chosen when the game started.
+    pub replay_name: String,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    Corrupt(String),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SaveError::Io(ref e) => write!(f, "{}", e),
+            SaveError::Corrupt(ref reason) => write!(f, "corrupt saved game, {}", reason),
+        }
+    }
+}
+
+impl SaveGame {
+    /// Loads the game saved at `path`, or `None` if there is none.
+    pub fn load(path: &Path) -> Result<Option<SaveGame>, SaveError> {
+        match storage::read_file(path).map_err(SaveError::Io)? {
+            Some(content) => SaveGame::parse(&content).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        storage::write_atomic(path, &self.to_string())
+    }
+
+    pub fn parse(content: &str) -> Result<SaveGame, SaveError> {
+        let corrupt = |reason: String| SaveError::Corrupt(reason);
+        let (game, replay) = match content.find(&format!("\n{}\n", REPLAY_MARKER)) {
+            Some(index) => (&content[..index], &content[index + REPLAY_MARKER.len() + 2..]),
+            None => return Err(corrupt("no replay".to_string())),
+        };
+
+        let mut lines = game.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(corrupt("not a saved game of this version".to_string()));
+        }
+        let mut fields = HashMap::new();
+        for line in lines {
+            let mut words = line.split_whitespace();
+            if let Some(key) = words.next() {
+                fields.insert(key, words.collect::<Vec<_>>());
+            }
+        }
+        let replay_name = match fields.remove("replay-name").as_deref() {
+            Some(&[name]) => name.to_string(),
+            _ => return Err(corrupt("missing replay-name".to_string())),
+        };
+
+        Ok(SaveGame {
+            tetris: Tetris::from_fields(&fields).map_err(corrupt)?,
+            replay: Replay::parse(replay).map_err(|e| match e {
+                ReplayError::Io(e) => SaveError::Io(e),
+                ReplayError::Parse { .. } => corrupt(e.to_string()),
+            })?,
+            replay_name,
+        })
+    }
+}
+
+impl fmt::Display for SaveGame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "replay-name {}", self.replay_name)?;
+        for (key, values) in self.tetris.to_fields() {
+            if values.is_empty() {
+                writeln!(f, "{}", key)?;
+            } else {
+                writeln!(f, "{} {}", key, values.join(" "))?;
+            }
+        }
+        writeln!(f, "{}", REPLAY_MARKER)?;
+        write!(f, "{}", self.replay)
+    }
+}
@@ -0,0 +1,328 @@
+use sdl3::EventPump;
+use sdl3::event::Event;
+use sdl3::keyboard::{Keycode, TextInputUtil};
+use sdl3::pixels::Color;
+use sdl3::render::Canvas;
+use sdl3::video::Window;
+
+use crate::config::{self, Config};
+use crate::font;
+use crate::grade;
+use crate::highscore::{self, Leaderboard};
+use crate::mode::{self, GameMode, Ranking};
+use crate::tetris::{GameState, ScoringProfile, Tetris, MAX_PREVIEW};
+
+const TITLE_COLOR: Color = Color::RGB(255, 69, 69);
+const TEXT_COLOR: Color = Color::RGB(255, 255, 255);
+const HIGHLIGHT_COLOR: Color = Color::RGB(255, 220, 69);
+const HINT_COLOR: Color = Color::RGB(160, 160, 160);
+
+fn layout(canvas: &Canvas<Window>) -> (i32, i32) {
+    let (width, height) = canvas.output_size().expect("Could not get window size");
+    (width as i32 / 2, height as i32 / 4)
+}
+
+fn wait_for_key(event_pump: &mut EventPump) {
+    loop {
+        match event_pump.wait_event() {
+            Event::Quit { .. } | Event::KeyDown { repeat: false, .. } => break,
+            _ => {}
+        }
+    }
+}
+
+pub fn draw_leaderboard(
+    canvas: &mut Canvas<Window>,
+    board: &Leaderboard,
+    highlight: Option<usize>,
+    center_x: i32,
+    y: i32
+) {
+    for (entry_nb, entry) in board.entries().iter().enumerate() {
+        let color = if highlight == Some(entry_nb + 1) { HIGHLIGHT_COLOR } else { TEXT_COLOR };
+        let name = if entry.name.is_empty() { "---" } else { &entry.name };
+        let result = match board.ranking() {
+            Ranking::Score => format!("{:>7} {:>4}", entry.score, entry.lines),
+            Ranking::Time => format!("{:>12}", mode::format_time(entry.time_ms)),
+            Ranking::Grade => format!("{:>2} {:>9}", grade::rank_name(entry.grade), mode::format_time(entry.time_ms)),
+        };
+        font::draw_text_centered(
+            canvas,
+            &format!("#{} {:<12} {}", entry_nb + 1, name, result),
+            center_x,
+            y + entry_nb as i32 * 30,
+            3,
+            color
+        );
+    }
+}
+
+/// Shows the result of a game. With `offer_continue` the player can choose
+/// to keep playing, which is what the returned flag says.
+pub fn show_game_over(
+    canvas: &mut Canvas<Window>,
+    event_pump: &mut EventPump,
+    tetris: &Tetris,
+    board: Option<&Leaderboard>,
+    rank: Option<usize>,
+    offer_continue: bool
+) -> bool {
+    let (center_x, top) = layout(canvas);
+
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.clear();
+    let title = match (tetris.state, tetris.mode) {
+        (GameState::Finished, GameMode::Puzzle { .. }) => "SOLVED!",
+        (_, GameMode::Puzzle { .. }) => "FAILED",
+        (GameState::Finished, GameMode::Marathon { .. }) => "VICTORY!",
+        (GameState::Finished, GameMode::Ultra { .. }) => "TIME UP",
+        (GameState::Finished, _) => "FINISHED",
+        _ => "GAME OVER",
+    };
+    font::draw_text_centered(canvas, title, center_x, top - 150, 6, TITLE_COLOR);
+    let subtitle = match tetris.mode.puzzle_goal() {
+        Some(goal) => goal.description(),
+        None => tetris.mode.title(),
+    };
+    font::draw_text_centered(canvas, &subtitle, center_x, top - 80, 3, HINT_COLOR);
+    let stats = match tetris.mode.ranking() {
+        Ranking::Score => format!("SCORE {}", tetris.score),
+        Ranking::Time | Ranking::Grade => format!("TIME {}", mode::format_time(mode::frames_to_ms(tetris.frames))),
+    };
+    font::draw_text_centered(canvas, &stats, center_x, top, 4, TEXT_COLOR);
+    font::draw_text_centered(canvas, &format!("LINES {}", tetris.number_of_lines), center_x, top + 50, 4, TEXT_COLOR);
+    let progress = match tetris.grade_name() {
+        Some(grade) => format!("GRADE {}", grade),
+        None => format!("LEVEL {}", tetris.current_level),
+    };
+    font::draw_text_centered(canvas, &progress, center_x, top + 100, 4, TEXT_COLOR);
+    font::draw_text_centered(
+        canvas,
+        &format!("FINESSE FAULTS {} IN {} PIECES", tetris.finesse.faults, tetris.finesse.judged),
+        center_x,
+        top + 145,
+        2,
+        HINT_COLOR
+    );
+    if let Some(rank) = rank {
+        font::draw_text_centered(canvas, &format!("YOU PLACED #{}", rank), center_x, top + 170, 3, HIGHLIGHT_COLOR);
+    }
+    if let Some(board) = board {
+        draw_leaderboard(canvas, board, rank, center_x, top + 220);
+    }
+    if !offer_continue {
+        font::draw_text_centered(canvas, "PRESS ANY KEY", center_x, top + 400, 2, HINT_COLOR);
+        canvas.present();
+        wait_for_key(event_pump);
+        return false;
+    }
+
+    font::draw_text_centered(canvas, "ENTER KEEP PLAYING  ESCAPE QUIT", center_x, top + 400, 2, HINT_COLOR);
+    canvas.present();
+    loop {
+        match event_pump.wait_event() {
+            Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return false,
+            Event::KeyDown { keycode: Some(Keycode::Return), .. } |
+            Event::KeyDown { keycode: Some(Keycode::KpEnter), .. } => return true,
+            _ => {}
+        }
+    }
+}
+
+/// Offers to pick up the saved game. `None` if the window was closed.
+pub fn offer_resume(canvas: &mut Canvas<Window>, event_pump: &mut EventPump, saved: &Tetris) -> Option<bool> {
+    let (center_x, top) = layout(canvas);
+
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.clear();
+    font::draw_text_centered(canvas, "SAVED GAME", center_x, top - 100, 5, TITLE_COLOR);
+    font::draw_text_centered(canvas, &saved.mode.title(), center_x, top, 3, HINT_COLOR);
+    font::draw_text_centered(
+        canvas,
+        &format!("SCORE {}  LINES {}  {}", saved.score, saved.number_of_lines, mode::format_time(mode::frames_to_ms(saved.frames))),
+        center_x,
+        top + 50,
+        3,
+        TEXT_COLOR
+    );
+    font::draw_text_centered(canvas, "ENTER RESUME  ESCAPE NEW GAME", center_x, top + 400, 2, HINT_COLOR);
+    canvas.present();
+
+    loop {
+        match event_pump.wait_event() {
+            Event::Quit { .. } => return None,
+            Event::KeyDown { keycode: Some(Keycode::Return), .. } |
+            Event::KeyDown { keycode: Some(Keycode::KpEnter), .. } => return Some(true),
+            Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return Some(false),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum QuitChoice {
+    SaveAndQuit,
+    Quit,
+    KeepPlaying,
+}
+
+/// Asks whether to put the game aside before quitting. Closing the window
+/// again saves, so the game is never lost by accident.
+pub fn confirm_quit(canvas: &mut Canvas<Window>, event_pump: &mut EventPump) -> QuitChoice {
+    let (center_x, top) = layout(canvas);
+
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.clear();
+    font::draw_text_centered(canvas, "QUIT?", center_x, top - 100, 5, TITLE_COLOR);
+    font::draw_text_centered(canvas, "S  SAVE AND QUIT", center_x, top, 3, TEXT_COLOR);
+    font::draw_text_centered(canvas, "Q  QUIT", center_x, top + 40, 3, TEXT_COLOR);
+    font::draw_text_centered(canvas, "ESCAPE  KEEP PLAYING", center_x, top + 80, 3, TEXT_COLOR);
+    canvas.present();
+
+    loop {
+        match event_pump.wait_event() {
+            Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::S), .. } => return QuitChoice::SaveAndQuit,
+            Event::KeyDown { keycode: Some(Keycode::Q), .. } => return QuitChoice::Quit,
+            Event::KeyDown { keycode: Some(Keycode::Escape), repeat: false, .. } => return QuitChoice::KeepPlaying,
+            _ => {}
+        }
+    }
+}
+
+/// Asks for the player's name using SDL text input events. Closing the
+/// window or pressing escape keeps whatever was typed so far.
+pub fn enter_name(
+    canvas: &mut Canvas<Window>,
+    event_pump: &mut EventPump,
+    text_input: &TextInputUtil,
+    rank: usize
+) -> String {
+    let (center_x, top) = layout(canvas);
+    let mut name = String::new();
+
+    text_input.start(canvas.window());
+    loop {
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        font::draw_text_centered(canvas, "NEW HIGHSCORE!", center_x, top - 100, 5, HIGHLIGHT_COLOR);
+        font::draw_text_centered(canvas, &format!("YOU PLACED #{}", rank), center_x, top, 4, TEXT_COLOR);
+        font::draw_text_centered(canvas, "ENTER YOUR NAME", center_x, top + 100, 3, TEXT_COLOR);
+        font::draw_text_centered(canvas, &format!("{}_", name), center_x, top + 160, 4, HIGHLIGHT_COLOR);
+        font::draw_text_centered(canvas, "PRESS ENTER TO CONFIRM", center_x, top + 400, 2, HINT_COLOR);
+        canvas.present();
+
+        match event_pump.wait_event() {
+            Event::Quit { .. } |
+            Event::KeyDown { keycode: Some(Keycode::Return), .. } |
+            Event::KeyDown { keycode: Some(Keycode::KpEnter), .. } |
+            Event::KeyDown { keycode: Some(Keycode::Escape), .. } => break,
+            Event::KeyDown { keycode: Some(Keycode::Backspace), .. } => {
+                name.pop();
+            },
+            Event::TextInput { text, .. } => {
+                for c in text.chars().filter(|&c| highscore::is_valid_name_char(c)) {
+                    if name.chars().count() < highscore::MAX_NAME_LEN {
+                        name.push(c);
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+    text_input.stop(canvas.window());
+
+    name.trim().to_string()
+}
+
+const SETTINGS: [&str; 7] = ["DAS", "ARR", "LOCK DELAY", "SOFT DROP", "PREVIEW", "GHOST PIECE", "SCORING"];
+
+fn setting_value(config: &Config, setting: usize) -> String {
+    match setting {
+        0 => format!("{} MS", config.handling.das),
+        1 => format!("{} MS", config.handling.arr),
+        2 => format!("{} MS", config.handling.lock_delay),
+        3 => format!("{} MS", config.handling.soft_drop),
+        4 => config.preview_count.to_string(),
+        5 => if config.visuals.ghost_piece { "ON" } else { "OFF" }.to_string(),
+        6 => config.scoring.name().to_string(),
+        _ => unreachable!(),
+    }
+}
+
+fn step_value(value: &mut u32, direction: i32, step: u32, max: u32) {
+    *value = if direction < 0 {
+        value.saturating_sub(step)
+    } else {
+        (*value + step).min(max)
+    };
+}
+
+fn adjust_setting(config: &mut Config, setting: usize, direction: i32) {
+    match setting {
+        0 => step_value(&mut config.handling.das, direction, 10, config::MAX_DAS),
+        1 => step_value(&mut config.handling.arr, direction, 5, config::MAX_ARR),
+        2 => step_value(&mut config.handling.lock_delay, direction, 50, config::MAX_LOCK_DELAY),
+        3 => step_value(&mut config.handling.soft_drop, direction, 10, config::MAX_SOFT_DROP),
+        4 => {
+            let mut preview_count = config.preview_count as u32;
+            step_value(&mut preview_count, direction, 1, MAX_PREVIEW as u32);
+            config.preview_count = preview_count as usize;
+        },
+        5 => config.visuals.ghost_piece = !config.visuals.ghost_piece,
+        6 => {
+            let profiles = ScoringProfile::ALL;
+            let index = profiles.iter().position(|&profile| profile == config.scoring).unwrap_or(0);
+            let index = (index as i32 + direction).rem_euclid(profiles.len() as i32);
+            config.scoring = profiles[index as usize];
+        },
+        _ => unreachable!(),
+    }
+}
+
+/// Lets the player change the settings that apply without a restart.
+/// Returns the edited config if it should be saved.
+pub fn settings_menu(
+    canvas: &mut Canvas<Window>,
+    event_pump: &mut EventPump,
+    config: &Config
+) -> Option<Config> {
+    let (center_x, top) = layout(canvas);
+    let mut edited = config.clone();
+    let mut selected = 0;
+
+    loop {
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        font::draw_text_centered(canvas, "SETTINGS", center_x, top - 100, 5, TITLE_COLOR);
+        for (setting, label) in SETTINGS.iter().enumerate() {
+            let color = if setting == selected { HIGHLIGHT_COLOR } else { TEXT_COLOR };
+            let marker = if setting == selected { ">" } else { " " };
+            font::draw_text_centered(
+                canvas,
+                &format!("{} {:<12} {:>10}", marker, label, setting_value(&edited, setting)),
+                center_x,
+                top + setting as i32 * 40,
+                3,
+                color
+            );
+        }
+        font::draw_text_centered(canvas, "SCORING APPLIES FROM THE NEXT GAME", center_x, top + 310, 2, HINT_COLOR);
+        font::draw_text_centered(canvas, "UP/DOWN SELECT  LEFT/RIGHT CHANGE", center_x, top + 360, 2, HINT_COLOR);
+        font::draw_text_centered(canvas, "ENTER SAVE  ESCAPE CANCEL", center_x, top + 390, 2, HINT_COLOR);
+        canvas.present();
+
+        match event_pump.wait_event() {
+            Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return None,
+            Event::KeyDown { keycode: Some(Keycode::Return), .. } => return Some(edited),
+            Event::KeyDown { keycode: Some(Keycode::Up), .. } => {
+                selected = (selected + SETTINGS.len() - 1) % SETTINGS.len();
+            },
+            Event::KeyDown { keycode: Some(Keycode::Down), .. } => {
+                selected = (selected + 1) % SETTINGS.len();
+            },
+            Event::KeyDown { keycode: Some(Keycode::Left), .. } => adjust_setting(&mut edited, selected, -1),
+            Event::KeyDown { keycode: Some(Keycode::Right), .. } => adjust_setting(&mut edited, selected, 1),
+            _ => {}
+        }
+    }
+}
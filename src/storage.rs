@@ -0,0 +1,122 @@
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+pub const SCORE_FILE_ENV: &str = "TETRIS_SCORE_FILE";
+const SCORE_FILE_NAME: &str = "scores.txt";
+const REPLAY_DIR_NAME: &str = "replays";
+const SAVE_FILE_NAME: &str = "savegame.txt";
+
+// `$<variable>/tetris`, falling back to `~/<fallback>/tetris` as the XDG
+// base directory spec asks when the variable is unset or relative.
+fn xdg_dir(variable: &str, fallback: &[&str]) -> PathBuf {
+    if let Some(dir) = env::var_os(variable) {
+        let dir = PathBuf::from(dir);
+        if dir.is_absolute() {
+            return dir.join("tetris");
+        }
+    }
+    match env::var_os("HOME") {
+        Some(home) => fallback.iter().fold(PathBuf::from(home), |path, part| path.join(part)).join("tetris"),
+        None => PathBuf::from("."),
+    }
+}
+
+/// `$XDG_DATA_HOME/tetris`, usually `~/.local/share/tetris`.
+pub fn data_dir() -> PathBuf {
+    xdg_dir("XDG_DATA_HOME", &[".local", "share"])
+}
+
+/// `$XDG_CONFIG_HOME/tetris`, usually `~/.config/tetris`.
+pub fn config_dir() -> PathBuf {
+    xdg_dir("XDG_CONFIG_HOME", &[".config"])
+}
+
+/// The score file to use: the command-line path if given, then
+/// `$TETRIS_SCORE_FILE`, then `scores.txt` in the data directory.
+pub fn score_file(from_args: Option<PathBuf>) -> PathBuf {
+    from_args
+        .or_else(|| env::var_os(SCORE_FILE_ENV).filter(|path| !path.is_empty()).map(PathBuf::from))
+        .unwrap_or_else(|| data_dir().join(SCORE_FILE_NAME))
+}
+
+/// Where games are recorded: a `replays` directory next to the score file.
+pub fn replay_dir(score_file: &Path) -> PathBuf {
+    match score_file.parent() {
+        Some(parent) => parent.join(REPLAY_DIR_NAME),
+        None => PathBuf::from(REPLAY_DIR_NAME),
+    }
+}
+
+/// Where a game put aside with "save and quit" waits, next to the score
+/// file.
+pub fn save_file(score_file: &Path) -> PathBuf {
+    match score_file.parent() {
+        Some(parent) => parent.join(SAVE_FILE_NAME),
+        None => PathBuf::from(SAVE_FILE_NAME),
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+pub fn backup_path(path: &Path) -> PathBuf {
+    with_suffix(path, "bak")
+}
+
+/// Reads the whole file, returning `None` if it does not exist yet.
+pub fn read_file(path: &Path) -> io::Result<Option<String>> {
+    let mut f = match File::open(path) {
+        Ok(f) => f,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut content = String::new();
+    f.read_to_string(&mut content)?;
+    Ok(Some(content))
+}
+
+/// Writes `content` to a temporary file next to `path` and renames it into
+/// place, so readers only ever see the old or the new file. The previous
+/// version is kept as `<path>.bak`.
+pub fn write_atomic(path: &Path, content: &str) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+
+    let tmp = with_suffix(path, "tmp");
+    {
+        let mut f = File::create(&tmp)?;
+        f.write_all(content.as_bytes())?;
+        f.sync_all()?;
+    }
+
+    if path.exists() {
+        fs::copy(path, backup_path(path))?;
+    }
+    fs::rename(&tmp, path)
+}
+
+/// Deletes the file if it is there.
+pub fn remove(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Moves an unreadable file out of the way so the next save does not
+/// replace the backup with it, and returns where it went.
+pub fn set_aside(path: &Path) -> io::Result<PathBuf> {
+    let aside = with_suffix(path, "corrupt");
+    fs::rename(path, &aside)?;
+    Ok(aside)
+}
@@ -1,233 +1,342 @@
-pub type Piece = Vec<Vec<u8>>;
-pub type States = Vec<Piece>;
+use crate::board::Board;
 
-pub struct Tetrimino {
-    pub states: States,
-    pub x: isize,
-    pub y: usize,
-    pub current_state: u8,
-}
-
-pub trait TetriminoGenerator {
-    fn new() -> Tetrimino;
-}
-
-pub struct TetriminoI;
-impl TetriminoGenerator for TetriminoI {
-    fn new() -> Tetrimino {
-        Tetrimino {
-            states: vec![
-                        vec![
-                            vec![1, 1, 1, 1],
-                            vec![0, 0, 0, 0],
-                            vec![0, 0, 0, 0],
-                            vec![0, 0, 0, 0],
-                        ],
-                        vec![
-                            vec![0, 1, 0, 0],
-                            vec![0, 1, 0, 0],
-                            vec![0, 1, 0, 0],
-                            vec![0, 1, 0, 0],
-                        ]
-                    ],
-            x: 4,
-            y: 0,
-            current_state: 0,
+// Turns drawings of a piece's rotations, '#' for a cell, into the filled
+// columns of each row, bit x for column x, one rotation after the other.
+const fn shape<const ROTATIONS: usize, const SIZE: usize, const ROWS: usize>(
+    rotations: [[&str; SIZE]; ROTATIONS],
+) -> [u16; ROWS] {
+    assert!(ROTATIONS * SIZE == ROWS);
+    let mut rows = [0; ROWS];
+    let mut row = 0;
+    while row < ROWS {
+        let line = rotations[row / SIZE][row % SIZE].as_bytes();
+        let mut x = 0;
+        while x < line.len() {
+            if line[x] == b'#' {
+                rows[row] |= 1 << x;
+            }
+            x += 1;
         }
+        row += 1;
     }
+    rows
 }
 
-pub struct TetriminoL;
-impl TetriminoGenerator for TetriminoL {
-    fn new() -> Tetrimino {
-        Tetrimino {
-            states: vec![
-                        vec![
-                            vec![2, 2, 2, 0],
-                            vec![2, 0, 0, 0],
-                            vec![0, 0, 0, 0],
-                            vec![0, 0, 0, 0]
-                        ],
-                        vec![
-                            vec![2, 2, 0, 0],
-                            vec![0, 2, 0, 0],
-                            vec![0, 2, 0, 0],
-                            vec![0, 0, 0, 0]
-                        ],
-                        vec![
-                            vec![0, 0, 2, 0],
-                            vec![2, 2, 2, 0],
-                            vec![0, 0, 0, 0],
-                            vec![0, 0, 0, 0]
-                        ],
-                        vec![
-                            vec![2, 0, 0, 0],
-                            vec![2, 0, 0, 0],
-                            vec![2, 2, 0, 0],
-                            vec![0, 0, 0, 0]
-                        ]
-                    ],
-                x: 4,
-                y: 0,
-                current_state: 0,
+/// A row of filled columns with every cell doubled across, for big mode.
+/// Only the first 8 columns fit.
+pub const fn double_row(row: u16) -> u16 {
+    let mut doubled = 0;
+    let mut x = 0;
+    while x < 8 {
+        if row & 1 << x != 0 {
+            doubled |= 0b11 << (x * 2);
         }
+        x += 1;
     }
+    doubled
 }
 
-pub struct TetriminoJ;
-impl TetriminoGenerator for TetriminoJ {
-    fn new() -> Tetrimino {
-        Tetrimino {
-            states: vec![
-                        vec![
-                            vec![3, 3, 3, 0],
-                            vec![0, 0, 3, 0],
-                            vec![0, 0, 0, 0],
-                            vec![0, 0, 0, 0]
-                        ],
-                        vec![
-                            vec![0, 3, 0, 0],
-                            vec![0, 3, 0, 0],
-                            vec![3, 3, 0, 0],
-                            vec![0, 0, 0, 0]
-                        ],
-                        vec![
-                            vec![3, 0, 0, 0],
-                            vec![3, 3, 3, 0],
-                            vec![0, 0, 0, 0],
-                            vec![0, 0, 0, 0]
-                        ],
-                        vec![
-                            vec![3, 3, 0, 0],
-                            vec![3, 0, 0, 0],
-                            vec![3, 0, 0, 0],
-                            vec![0, 0, 0, 0]
-                        ]
-                    ],
-                x: 4,
-                y: 0,
-                current_state: 0,
-        }
+// The same rows with every cell doubled across and down.
+const fn doubled<const ROWS: usize, const BIG_ROWS: usize>(rows: [u16; ROWS]) -> [u16; BIG_ROWS] {
+    assert!(ROWS * 2 == BIG_ROWS);
+    let mut big = [0; BIG_ROWS];
+    let mut row = 0;
+    while row < ROWS {
+        big[row * 2] = double_row(rows[row]);
+        big[row * 2 + 1] = big[row * 2];
+        row += 1;
     }
+    big
 }
 
-pub struct TetriminoO;
-impl TetriminoGenerator for TetriminoO {
-    fn new() -> Tetrimino {
-        Tetrimino {
-            states: vec![
-                        vec![
-                            vec![4, 4, 0, 0],
-                            vec![4, 4, 0, 0],
-                            vec![0, 0, 0, 0],
-                            vec![0, 0, 0, 0]
-                        ],
-                    ],
-                x: 4,
-                y: 0,
-                current_state: 0,
-        }
+/// The columns a rotation tries, in order, until the piece fits.
+pub const KICKS: [isize; 6] = [0, -1, 1, -2, 2, -3];
+
+const I_ROTATIONS: [u16; 8] = shape([
+    ["####",
+     "....",
+     "....",
+     "...."],
+    [".#..",
+     ".#..",
+     ".#..",
+     ".#.."],
+]);
+const L_ROTATIONS: [u16; 16] = shape([
+    ["###.",
+     "#...",
+     "....",
+     "...."],
+    ["##..",
+     ".#..",
+     ".#..",
+     "...."],
+    ["..#.",
+     "###.",
+     "....",
+     "...."],
+    ["#...",
+     "#...",
+     "##..",
+     "...."],
+]);
+const J_ROTATIONS: [u16; 16] = shape([
+    ["###.",
+     "..#.",
+     "....",
+     "...."],
+    [".#..",
+     ".#..",
+     "##..",
+     "...."],
+    ["#...",
+     "###.",
+     "....",
+     "...."],
+    ["##..",
+     "#...",
+     "#...",
+     "...."],
+]);
+const O_ROTATIONS: [u16; 4] = shape([
+    ["##..",
+     "##..",
+     "....",
+     "...."],
+]);
+const S_ROTATIONS: [u16; 8] = shape([
+    [".##.",
+     "##..",
+     "....",
+     "...."],
+    [".#..",
+     ".##.",
+     "..#.",
+     "...."],
+]);
+const Z_ROTATIONS: [u16; 8] = shape([
+    ["##..",
+     ".##.",
+     "....",
+     "...."],
+    ["..#.",
+     ".##.",
+     ".#..",
+     "...."],
+]);
+const T_ROTATIONS: [u16; 16] = shape([
+    ["###.",
+     ".#..",
+     "....",
+     "...."],
+    [".#..",
+     "##..",
+     ".#..",
+     "...."],
+    [".#..",
+     "###.",
+     "....",
+     "...."],
+    [".#..",
+     ".##.",
+     ".#..",
+     "...."],
+]);
+
+/// Everything about a kind of piece that doesn't change during a game.
+#[derive(PartialEq, Eq, Hash, Debug)]
+pub struct PieceDef {
+    // The letter puzzle files use for it.
+    pub name: &'static str,
+    // The number its cells leave on the board, which picks their color.
+    pub color: u8,
+    // Rows and columns of the square each rotation is drawn in.
+    pub size: usize,
+    // Columns from the middle of the board to the piece's left edge when
+    // it spawns.
+    pub spawn_offset: isize,
+    // Column offsets, in piece cells, a rotation tries until one fits.
+    pub kicks: &'static [isize],
+    // How often the randomizer deals it compared to the rest of its set.
+    pub weight: u32,
+    // `size` rows of filled columns per rotation, and `2 * size` for big
+    // mode.
+    pub rotations: &'static [u16],
+    pub big_rotations: &'static [u16],
+}
+
+impl PieceDef {
+    /// Columns the piece spans in its widest rotation.
+    pub fn width(&self) -> usize {
+        self.rotations.iter().map(|&row| 16 - row.leading_zeros() as usize).max().unwrap_or(0)
     }
 }
 
-pub struct TetriminoS;
-impl TetriminoGenerator for TetriminoS {
-    fn new() -> Tetrimino {
-        Tetrimino {
-            states: vec![
-                        vec![
-                            vec![0, 5, 5, 0],
-                            vec![5, 5, 0, 0],
-                            vec![0, 0, 0, 0],
-                            vec![0, 0, 0, 0]
-                        ],
-                        vec![
-                            vec![0, 5, 0, 0],
-                            vec![0, 5, 5, 0],
-                            vec![0, 0, 5, 0],
-                            vec![0, 0, 0, 0]
-                        ],
-                    ],
-                x: 4,
-                y: 0,
-                current_state: 0,
+static PIECES: [PieceDef; 7] = [
+    PieceDef { name: "I", color: 1, size: 4, spawn_offset: -1, kicks: &KICKS, weight: 1, rotations: &I_ROTATIONS, big_rotations: &doubled::<8, 16>(I_ROTATIONS) },
+    PieceDef { name: "L", color: 2, size: 4, spawn_offset: -1, kicks: &KICKS, weight: 1, rotations: &L_ROTATIONS, big_rotations: &doubled::<16, 32>(L_ROTATIONS) },
+    PieceDef { name: "J", color: 3, size: 4, spawn_offset: -1, kicks: &KICKS, weight: 1, rotations: &J_ROTATIONS, big_rotations: &doubled::<16, 32>(J_ROTATIONS) },
+    PieceDef { name: "O", color: 4, size: 4, spawn_offset: -1, kicks: &KICKS, weight: 1, rotations: &O_ROTATIONS, big_rotations: &doubled::<4, 8>(O_ROTATIONS) },
+    PieceDef { name: "S", color: 5, size: 4, spawn_offset: -1, kicks: &KICKS, weight: 1, rotations: &S_ROTATIONS, big_rotations: &doubled::<8, 16>(S_ROTATIONS) },
+    PieceDef { name: "Z", color: 6, size: 4, spawn_offset: -1, kicks: &KICKS, weight: 1, rotations: &Z_ROTATIONS, big_rotations: &doubled::<8, 16>(Z_ROTATIONS) },
+    PieceDef { name: "T", color: 7, size: 4, spawn_offset: -1, kicks: &KICKS, weight: 1, rotations: &T_ROTATIONS, big_rotations: &doubled::<16, 32>(T_ROTATIONS) },
+];
+
+/// The seven tetrominoes, in the order of their color numbers, and the
+/// pieces of custom sets. `pieceset` has its serde impls, which store a
+/// custom piece by its set.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum PieceKind {
+    I,
+    L,
+    J,
+    O,
+    S,
+    Z,
+    T,
+    // Loaded from a piece set file, see `pieceset`.
+    Custom(&'static PieceDef),
+}
+
+impl PieceKind {
+    /// The standard set, dealt unless a game has a piece set.
+    pub const ALL: [PieceKind; 7] = [
+        PieceKind::I,
+        PieceKind::L,
+        PieceKind::J,
+        PieceKind::O,
+        PieceKind::S,
+        PieceKind::Z,
+        PieceKind::T,
+    ];
+
+    pub fn def(self) -> &'static PieceDef {
+        match self {
+            PieceKind::I => &PIECES[0],
+            PieceKind::L => &PIECES[1],
+            PieceKind::J => &PIECES[2],
+            PieceKind::O => &PIECES[3],
+            PieceKind::S => &PIECES[4],
+            PieceKind::Z => &PIECES[5],
+            PieceKind::T => &PIECES[6],
+            PieceKind::Custom(def) => def,
         }
     }
-}
 
-pub struct TetriminoZ;
-impl TetriminoGenerator for TetriminoZ {
-    fn new() -> Tetrimino {
-        Tetrimino {
-            states: vec![
-                        vec![
-                            vec![6, 6, 0, 0],
-                            vec![0, 6, 6, 0],
-                            vec![0, 0, 0, 0],
-                            vec![0, 0, 0, 0]
-                        ],
-                        vec![
-                            vec![0, 0, 6, 0],
-                            vec![0, 6, 6, 0],
-                            vec![0, 6, 0, 0],
-                            vec![0, 0, 0, 0]
-                        ],
-                    ],
-                x: 4,
-                y: 0,
-                current_state: 0,
+    pub fn name(self) -> &'static str {
+        self.def().name
+    }
+
+    /// The tetromino named `name`, in either case.
+    pub fn from_name(name: &str) -> Option<PieceKind> {
+        PieceKind::ALL.iter().cloned().find(|kind| kind.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn color(self) -> u8 {
+        self.def().color
+    }
+
+    /// The tetromino of color `color`.
+    pub fn from_color(color: u8) -> Option<PieceKind> {
+        PieceKind::ALL.iter().cloned().find(|kind| kind.color() == color)
+    }
+
+    /// The cells of the piece in its spawn rotation, for previews.
+    pub fn preview(self) -> Vec<Vec<u8>> {
+        let size = self.def().size;
+        let mut cells = vec![vec![0; size]; size];
+        for (x, y) in Tetrimino::new(self).cells_at(0, 0, 0) {
+            cells[y as usize][x as usize] = self.color();
         }
+        cells
     }
 }
 
-pub struct TetriminoT;
-impl TetriminoGenerator for TetriminoT {
-    fn new() -> Tetrimino {
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Tetrimino {
+    pub kind: PieceKind,
+    pub x: isize,
+    pub y: usize,
+    pub rotation: u8,
+    // Board cells per side of each piece cell, 2 in big mode.
+    scale: usize,
+}
+
+impl Tetrimino {
+    pub fn new(kind: PieceKind) -> Tetrimino {
         Tetrimino {
-            states: vec![
-                        vec![
-                            vec![7, 7, 7, 0],
-                            vec![0, 7, 0, 0],
-                            vec![0, 0, 0, 0],
-                            vec![0, 0, 0, 0]
-                        ],
-                        vec![
-                            vec![0, 7, 0, 0],
-                            vec![7, 7, 0, 0],
-                            vec![0, 7, 0, 0],
-                            vec![0, 0, 0, 0]
-                        ],
-                        vec![
-                            vec![0, 7, 0, 0],
-                            vec![7, 7, 7, 0],
-                            vec![0, 0, 0, 0],
-                            vec![0, 0, 0, 0]
-                        ],
-                        vec![
-                            vec![0, 7, 0, 0],
-                            vec![0, 7, 7, 0],
-                            vec![0, 7, 0, 0],
-                            vec![0, 0, 0, 0]
-                        ]
-                    ],
-                x: 4,
-                y: 0,
-                current_state: 0,
+            kind,
+            x: 4,
+            y: 0,
+            rotation: 0,
+            scale: 1,
         }
     }
-}
 
-impl Tetrimino {
-    pub fn rotate(&mut self, game_map: &[Vec<u8>]) -> bool{
-        let mut tmp_state = self.current_state + 1;
-        if tmp_state as usize >= self.states.len() {
+    pub fn scale(&self) -> usize {
+        self.scale
+    }
+
+    /// 1, or 2 for big mode.
+    pub fn set_scale(&mut self, scale: usize) {
+        assert!(scale == 1 || scale == 2);
+        self.scale = scale;
+    }
+
+    pub fn rotations(&self) -> usize {
+        let def = self.kind.def();
+        def.rotations.len() / def.size
+    }
+
+    // The filled columns of each row of `rotation`, at the piece's scale.
+    fn mask(&self, rotation: usize) -> &'static [u16] {
+        let def = self.kind.def();
+        let rows = if self.scale == 1 { def.rotations } else { def.big_rotations };
+        let size = def.size * self.scale;
+        &rows[rotation * size..(rotation + 1) * size]
+    }
+
+    pub fn rotate(&mut self, game_map: &Board) -> bool {
+        let mut tmp_state = self.rotation + 1;
+        if tmp_state as usize >= self.rotations() {
             tmp_state = 0;
         }
+        self.rotate_to(game_map, tmp_state)
+    }
 
-        let x_pos = [0, -1, 1, -2, 2, -3];
-        for &x in x_pos.iter() {
+    pub fn rotate_counterclockwise(&mut self, game_map: &Board) -> bool {
+        let tmp_state = if self.rotation == 0 {
+            self.rotations() as u8 - 1
+        } else {
+            self.rotation - 1
+        };
+        self.rotate_to(game_map, tmp_state)
+    }
+
+    /// Rotates one row higher, for pieces resting on the stack that can't
+    /// rotate where they are.
+    pub fn floor_kick(&mut self, game_map: &Board, clockwise: bool) -> bool {
+        if self.y == 0 {
+            return false;
+        }
+        self.y -= 1;
+        let rotated = if clockwise {
+            self.rotate(game_map)
+        } else {
+            self.rotate_counterclockwise(game_map)
+        };
+        if !rotated {
+            self.y += 1;
+        }
+        rotated
+    }
+
+    fn rotate_to(&mut self, game_map: &Board, tmp_state: u8) -> bool {
+        for &x in self.kind.def().kicks.iter() {
+            let x = x * self.scale as isize;
             if self.test_position(game_map, tmp_state as usize, self.x + x, self.y) {
-                self.current_state = tmp_state;
+                self.rotation = tmp_state;
                 self.x += x;
                 return true
             }
@@ -235,10 +344,10 @@ impl Tetrimino {
         false
     }
 
-    pub fn change_position(&mut self, game_map: &[Vec<u8>], new_x: isize, new_y: usize) -> bool {
+    pub fn change_position(&mut self, game_map: &Board, new_x: isize, new_y: usize) -> bool {
         if self.test_position(
             game_map,
-            self.current_state as usize,
+            self.rotation as usize,
             new_x,
             new_y
         ) == true {
@@ -250,38 +359,216 @@ impl Tetrimino {
         }
     }
 
-    pub fn test_current_position(&self, game_map: &[Vec<u8>]) -> bool {
+    /// The board positions of the piece's cells.
+    pub fn cells(&self) -> Vec<(isize, isize)> {
+        self.cells_at(self.rotation as usize, self.x, self.y)
+    }
+
+    fn cells_at(&self, state: usize, x: isize, y: usize) -> Vec<(isize, isize)> {
+        let def = self.kind.def();
+        let mut cells = Vec::new();
+        for (decal_y, &line) in def.rotations[state * def.size..(state + 1) * def.size].iter().enumerate() {
+            for decal_x in 0..def.size {
+                if line & 1 << decal_x == 0 {
+                    continue;
+                }
+                for sub_y in 0..self.scale {
+                    for sub_x in 0..self.scale {
+                        cells.push((
+                            x + (decal_x * self.scale + sub_x) as isize,
+                            (y + decal_y * self.scale + sub_y) as isize,
+                        ));
+                    }
+                }
+            }
+        }
+        cells
+    }
+
+    pub fn test_current_position(&self, game_map: &Board) -> bool {
         self.test_position(
             game_map,
-            self.current_state as usize,
+            self.rotation as usize,
             self.x,
             self.y
         )
     }
 
-    fn test_position(
+    pub fn test_position(
         &self,
-        game_map: &[Vec<u8>],
+        game_map: &Board,
         tmp_state: usize,
         x: isize,
         y: usize,
     ) -> bool {
-        for decal_y in 0..4 {
-            for decal_x in 0..4 {
-                let x = x + decal_x;
-                let y = y + decal_y;
-
-                if self.states[tmp_state][decal_y][decal_x as usize] != 0
-                    && (
-                        y >= game_map.len() ||
-                        x < 0 ||
-                        x as usize >= game_map[y].len() ||
-                        game_map[y][x as usize] != 0
-                    ) {
-                        return false;
-                }
+        game_map.fits(self.mask(tmp_state), x, y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn piece(kind: PieceKind, rotation: u8, x: isize, y: usize) -> Tetrimino {
+        let mut tetrimino = Tetrimino::new(kind);
+        tetrimino.rotation = rotation;
+        tetrimino.x = x;
+        tetrimino.y = y;
+        tetrimino
+    }
+
+    #[test]
+    fn test_position_keeps_pieces_inside_the_board() {
+        let board = Board::new(10, 16);
+        let flat = Tetrimino::new(PieceKind::I);
+        assert!(flat.test_position(&board, 0, 0, 0));
+        assert!(flat.test_position(&board, 0, 6, 0));
+        assert!(!flat.test_position(&board, 0, 7, 0));
+        assert!(!flat.test_position(&board, 0, -1, 0));
+        assert!(flat.test_position(&board, 0, 0, 15));
+        assert!(!flat.test_position(&board, 0, 0, 16));
+        assert!(!flat.test_position(&board, 0, -100, 0));
+        assert!(!flat.test_position(&board, 0, 100, 0));
+    }
+
+    #[test]
+    fn empty_columns_and_rows_of_a_rotation_may_leave_the_board() {
+        let board = Board::new(10, 16);
+        // The upright I fills column 1 of its 4x4 square.
+        let upright = Tetrimino::new(PieceKind::I);
+        assert!(upright.test_position(&board, 1, -1, 0));
+        assert!(!upright.test_position(&board, 1, -2, 0));
+        assert!(upright.test_position(&board, 1, 8, 0));
+        assert!(!upright.test_position(&board, 1, 9, 0));
+        assert!(upright.test_position(&board, 1, 0, 12));
+        assert!(!upright.test_position(&board, 1, 0, 13));
+    }
+
+    #[test]
+    fn test_position_checks_locked_blocks() {
+        let mut board = Board::new(10, 16);
+        board.set(5, 10, 1);
+        let o = Tetrimino::new(PieceKind::O);
+        assert!(!o.test_position(&board, 0, 4, 9));
+        assert!(!o.test_position(&board, 0, 5, 10));
+        assert!(o.test_position(&board, 0, 6, 9));
+        assert!(o.test_position(&board, 0, 4, 8));
+        assert!(o.test_position(&board, 0, 3, 9));
+    }
+
+    #[test]
+    fn big_pieces_cover_two_cells_per_cell() {
+        let board = Board::new(10, 16);
+        let mut o = Tetrimino::new(PieceKind::O);
+        o.set_scale(2);
+        assert_eq!(o.cells().len(), 16);
+        assert!(o.test_position(&board, 0, 6, 12));
+        assert!(!o.test_position(&board, 0, 7, 12));
+        assert!(!o.test_position(&board, 0, 6, 13));
+    }
+
+    #[test]
+    fn change_position_moves_only_where_the_piece_fits() {
+        let mut board = Board::new(10, 16);
+        board.set(0, 15, 1);
+        let mut t = piece(PieceKind::T, 0, 3, 0);
+        assert!(t.change_position(&board, 0, 10));
+        assert_eq!((t.x, t.y), (0, 10));
+        assert!(!t.change_position(&board, -1, 10));
+        assert!(!t.change_position(&board, 0, 15));
+        // The stub of the T lands on column 1, next to the block.
+        assert!(t.change_position(&board, 0, 14));
+        assert_eq!((t.x, t.y), (0, 14));
+    }
+
+    #[test]
+    fn rotate_kicks_off_the_right_wall() {
+        let board = Board::new(10, 16);
+        let mut i = piece(PieceKind::I, 1, 8, 0);
+        assert!(i.rotate(&board));
+        assert_eq!((i.rotation, i.x), (0, 6));
+        assert!(i.cells().iter().all(|&(x, _)| (0..10).contains(&x)));
+    }
+
+    #[test]
+    fn rotate_kicks_off_the_left_wall() {
+        let board = Board::new(10, 16);
+        let mut i = piece(PieceKind::I, 1, -1, 0);
+        assert!(i.rotate_counterclockwise(&board));
+        assert_eq!((i.rotation, i.x), (0, 0));
+    }
+
+    #[test]
+    fn rotate_tries_the_kicks_in_order() {
+        let mut board = Board::new(10, 16);
+        // Blocks the T's stem where it is and one column left, so the
+        // first kick that fits is one to the right.
+        board.set(4, 2, 1);
+        board.set(3, 2, 1);
+        let mut t = piece(PieceKind::T, 0, 3, 0);
+        assert!(t.rotate(&board));
+        assert_eq!((t.rotation, t.x), (1, 4));
+    }
+
+    #[test]
+    fn rotation_wraps_around_both_ways() {
+        let board = Board::new(10, 16);
+        let mut t = piece(PieceKind::T, 0, 3, 0);
+        assert!(t.rotate_counterclockwise(&board));
+        assert_eq!(t.rotation, 3);
+        assert!(t.rotate(&board));
+        assert_eq!(t.rotation, 0);
+        let mut o = piece(PieceKind::O, 0, 3, 0);
+        assert!(o.rotate(&board));
+        assert_eq!(o.rotation, 0);
+    }
+
+    #[test]
+    fn rotate_fails_into_the_floor_without_moving() {
+        let board = Board::new(10, 16);
+        let mut i = piece(PieceKind::I, 0, 3, 15);
+        assert!(!i.rotate(&board));
+        assert_eq!((i.rotation, i.x, i.y), (0, 3, 15));
+        assert!(!i.floor_kick(&board, true));
+        assert_eq!((i.rotation, i.x, i.y), (0, 3, 15));
+    }
+
+    #[test]
+    fn floor_kick_rotates_a_row_higher() {
+        let board = Board::new(10, 16);
+        let mut t = piece(PieceKind::T, 0, 3, 14);
+        assert!(!t.rotate(&board));
+        assert!(t.floor_kick(&board, true));
+        assert_eq!((t.rotation, t.x, t.y), (1, 3, 13));
+        assert!(t.test_current_position(&board));
+
+        let mut top = piece(PieceKind::T, 0, 3, 0);
+        assert!(!top.floor_kick(&board, true));
+        assert_eq!(top.y, 0);
+    }
+
+    #[test]
+    fn rotate_fails_when_no_kick_fits() {
+        // Every upright rotation of the T needs a third row.
+        let mut board = Board::new(10, 16);
+        for x in 0..10 {
+            board.set(x, 2, 1);
+        }
+        let mut t = piece(PieceKind::T, 0, 3, 0);
+        let before = t;
+        assert!(!t.rotate(&board));
+        assert!(!t.rotate_counterclockwise(&board));
+        assert_eq!(t, before);
+    }
+
+    #[test]
+    fn every_rotation_has_four_cells() {
+        for &kind in PieceKind::ALL.iter() {
+            let mut tetrimino = Tetrimino::new(kind);
+            for rotation in 0..tetrimino.rotations() {
+                tetrimino.rotation = rotation as u8;
+                assert_eq!(tetrimino.cells().len(), 4, "{} rotation {}", kind.name(), rotation);
             }
         }
-        true
     }
 }
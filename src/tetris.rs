@@ -1,123 +1,926 @@
-use std::f32::consts::E;
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
 
-use crate::tetrimino::{
-    Tetrimino, TetriminoGenerator, TetriminoI, TetriminoJ, TetriminoL,
-    TetriminoO, TetriminoS, TetriminoT, TetriminoZ,
-};
+use rand::Rng;
+
+use crate::board::Board;
+use crate::finesse::{self, Finesse};
+use crate::grade::Grade;
+use crate::mode::{self, GameMode, PuzzleGoal, Variants};
+use crate::pieceset::PieceSet;
+use crate::puzzle;
+use crate::randomizer::Randomizer;
+use crate::tetrimino::{PieceKind, Tetrimino};
 
 pub const LEVEL_TIMES: [u32; 10] = [1000, 850, 700, 600, 500, 400, 300, 250, 221, 190];
 pub const LEVEL_LINES: [u32; 10] = [20, 40, 60, 80, 100, 120, 140, 160, 180, 200];
+// Past the end of the tables the gravity stays at its fastest and a new
+// level starts every this many lines.
+const LINES_PER_EXTRA_LEVEL: u32 = 20;
+
+pub const DEFAULT_WIDTH: usize = 10;
+pub const DEFAULT_HEIGHT: usize = 16;
+pub const MIN_WIDTH: usize = 4;
+pub const MAX_WIDTH: usize = 16;
+pub const MIN_HEIGHT: usize = 4;
+pub const MAX_HEIGHT: usize = 40;
+
+pub const FRAMES_PER_SECOND: u32 = 60;
+// One cell per frame. Gravity is kept in fractions of this so anything from
+// a cell a second to 20G fits.
+pub const G: u32 = 65536;
+pub const MAX_PREVIEW: usize = 6;
+// Moves and rotations on the ground restart the lock delay, but only this
+// many times per piece so it can't be stalled forever.
+pub const MAX_LOCK_RESETS: u32 = 15;
+
+// The color number of garbage cells, after the seven pieces.
+pub const GARBAGE: u8 = 8;
+// How many garbage rows dig mode keeps on the board at once.
+pub const DIG_VISIBLE_ROWS: usize = 10;
+
+const GUIDELINE_LINE_SCORES: [u32; 5] = [0, 100, 300, 500, 800];
+const GUIDELINE_PERFECT_CLEAR_SCORES: [u32; 5] = [0, 800, 1200, 1800, 2000];
+const CLASSIC_PERFECT_CLEAR_SCORE: u32 = 1000;
+// Perfect clear training restarts once the stack is higher than this.
+const PC_TRAINING_ROWS: usize = 4;
+// How long an invisible stack shows after lines are cleared.
+const REVEAL_FRAMES: u32 = 60;
+
+pub fn ms_to_frames(ms: u32) -> u32 {
+    (ms * FRAMES_PER_SECOND).div_ceil(1000)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ScoringProfile {
+    // The original rules: the level for every locked piece and cleared line,
+    // plus 1000 for emptying the board.
+    Classic,
+    // 100/300/500/800 times the level for 1 to 4 lines, 800 to 2000 times
+    // the level more if that empties the board, 1 point per soft dropped
+    // cell and 2 per hard dropped cell.
+    Guideline,
+}
+
+impl ScoringProfile {
+    pub const ALL: [ScoringProfile; 2] = [ScoringProfile::Classic, ScoringProfile::Guideline];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ScoringProfile::Classic => "classic",
+            ScoringProfile::Guideline => "guideline",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ScoringProfile> {
+        ScoringProfile::ALL.iter().cloned().find(|profile| profile.name() == name)
+    }
+}
+
+/// A saved game's fields by name, each a list of values.
+pub type Fields<'a> = HashMap<&'a str, Vec<&'a str>>;
+
+fn values<'a>(fields: &'a Fields, key: &str) -> Result<&'a [&'a str], String> {
+    fields.get(key).map(Vec::as_slice).ok_or_else(|| format!("missing {}", key))
+}
 
+// A field that holds a single value.
+fn field<T: FromStr>(fields: &Fields, key: &str) -> Result<T, String> {
+    match *values(fields, key)? {
+        [value] => value.parse().map_err(|_| format!("invalid {}", key)),
+        _ => Err(format!("invalid {}", key)),
+    }
+}
+
+// Pieces are saved as one base 36 digit each, their place in the set
+// counted from 1. For the standard set that is their color.
+fn piece_code(piece: PieceKind, set: &[PieceKind]) -> char {
+    let index = set.iter().position(|&kind| kind == piece).expect("piece from the game's set");
+    std::char::from_digit(index as u32 + 1, 36).expect("set of at most 35 pieces")
+}
+
+fn piece_from_code(code: char, set: &[PieceKind]) -> Option<PieceKind> {
+    let index = code.to_digit(36)?.checked_sub(1)?;
+    set.get(index as usize).cloned()
+}
+
+// A field of pieces written as their code each, possibly empty.
+fn piece_field(fields: &Fields, key: &str, set: &[PieceKind]) -> Result<Vec<PieceKind>, String> {
+    let pieces = match *values(fields, key)? {
+        [] => Some(Vec::new()),
+        [pieces] => pieces.chars().map(|piece| piece_from_code(piece, set)).collect(),
+        _ => None,
+    };
+    pieces.ok_or_else(|| format!("invalid {}", key))
+}
+
+fn pieces_to_field<'a, I: Iterator<Item = &'a PieceKind>>(pieces: I, set: &[PieceKind]) -> Vec<String> {
+    let pieces = pieces.map(|&piece| piece_code(piece, set)).collect::<String>();
+    if pieces.is_empty() { Vec::new() } else { vec![pieces] }
+}
+
+/// Picks the hole of each garbage row. The holes come from their own
+/// randomizer so garbage doesn't change which pieces a seed deals.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct GarbageGenerator {
+    randomizer: Randomizer,
+    // Percent chance that a row's hole is not under the previous one.
+    messiness: u32,
+    hole: Option<usize>,
+}
+
+impl GarbageGenerator {
+    pub fn new(seed: u64, messiness: u32) -> GarbageGenerator {
+        GarbageGenerator {
+            randomizer: Randomizer::new(seed),
+            messiness,
+            hole: None,
+        }
+    }
+
+    /// The state as values for a saved game.
+    pub fn to_fields(&self) -> Vec<String> {
+        let mut values = self.randomizer.to_fields();
+        values.push(self.messiness.to_string());
+        values.push(self.hole.map_or("-".to_string(), |hole| hole.to_string()));
+        values
+    }
+
+    /// Rebuilds the generator for a board `columns` holes wide.
+    pub fn from_fields(values: &[&str], columns: usize) -> Option<GarbageGenerator> {
+        let (&hole, values) = values.split_last()?;
+        let (&messiness, randomizer) = values.split_last()?;
+        Some(GarbageGenerator {
+            randomizer: Randomizer::from_fields(randomizer)?,
+            messiness: messiness.parse().ok().filter(|&messiness| messiness <= mode::MAX_MESSINESS)?,
+            hole: if hole == "-" { None } else { Some(hole.parse().ok().filter(|&hole| hole < columns)?) },
+        })
+    }
 
+    pub fn next_hole(&mut self, width: usize) -> usize {
+        let hole = match self.hole {
+            Some(hole) if !self.randomizer.random_ratio(self.messiness, 100) => hole,
+            // Any other column, never the same one.
+            Some(hole) => (hole + self.randomizer.random_range(1..width)) % width,
+            None => self.randomizer.random_range(0..width),
+        };
+        self.hole = Some(hole);
+        hole
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum GameState {
+    Playing,
+    ToppedOut,
+    // The mode's goal was reached.
+    Finished,
+    // A scripted piece sequence ran out before the goal was reached.
+    OutOfPieces,
+}
+
+impl GameState {
+    pub const ALL: [GameState; 4] = [
+        GameState::Playing,
+        GameState::ToppedOut,
+        GameState::Finished,
+        GameState::OutOfPieces,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GameState::Playing => "playing",
+            GameState::ToppedOut => "topped-out",
+            GameState::Finished => "finished",
+            GameState::OutOfPieces => "out-of-pieces",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<GameState> {
+        GameState::ALL.iter().cloned().find(|state| state.name() == name)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Tetris {
-    pub game_map: Vec<Vec<u8>>,
+    pub game_map: Board,
     pub current_level: u32,
     pub score: u32,
     pub number_of_lines: u32,
     pub current_tetrimino: Option<Tetrimino>,
+    pub width: usize,
+    pub height: usize,
+    pub next_pieces: VecDeque<PieceKind>,
+    pub hold: Option<PieceKind>,
+    pub hold_used: bool,
+    pub scoring: ScoringProfile,
+    pub lock_delay: u32,
+    pub mode: GameMode,
+    pub variants: Variants,
+    pub state: GameState,
+    // Frames played so far, the game clock.
+    pub frames: u32,
+    // Pieces locked so far.
+    pub pieces: u32,
+    pub garbage_cleared: u32,
+    garbage: GarbageGenerator,
+    // Garbage rows dig mode has yet to put on the board.
+    garbage_left: u32,
+    garbage_timer: u32,
+    // Whether the falling piece's last successful move was a rotation, for
+    // spotting T-spins.
+    last_rotated: bool,
+    // Deal only the pieces already in `next_pieces`.
+    scripted: bool,
+    pub grade: Option<Grade>,
+    pub finesse: Finesse,
+    pub perfect_clears: u32,
+    pub pc_streak: u32,
+    pub best_pc_streak: u32,
+    pc_attempts: usize,
+    reveal_timer: u32,
+    gravity_progress: u32,
+    are_timer: u32,
+    lock_timer: u32,
+    lock_resets: u32,
+    randomizer: Randomizer,
+}
+
+// The line count at which `level` ends.
+fn level_end(level: u32) -> u32 {
+    let last = LEVEL_LINES.len() as u32;
+    if level <= last {
+        LEVEL_LINES[level as usize - 1]
+    } else {
+        LEVEL_LINES[last as usize - 1] + (level - last) * LINES_PER_EXTRA_LEVEL
+    }
 }
 
 impl Tetris {
-    pub fn new() -> Tetris {
-        let mut game_map = Vec::new();
-        for _ in 0..16 {
-            game_map.push(vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
-        }
+    pub fn new(mode: GameMode, variants: Variants, width: usize, height: usize, seed: u64) -> Tetris {
+        let mut randomizer = Randomizer::new(seed);
+        let next_pieces = (0..MAX_PREVIEW).map(|_| randomizer.next_piece(variants.piece_kinds())).collect();
+
+        let messiness = match mode {
+            GameMode::Dig { messiness, .. } | GameMode::Survival { messiness } => messiness,
+            _ => 0,
+        };
 
-        Tetris {
-            game_map,
+        let mut tetris = Tetris {
+            game_map: Board::new(width, height),
             current_level: 1,
             score: 0,
             number_of_lines: 0,
             current_tetrimino: None,
+            width,
+            height,
+            next_pieces,
+            hold: None,
+            hold_used: false,
+            scoring: ScoringProfile::Classic,
+            lock_delay: 0,
+            mode,
+            variants,
+            state: GameState::Playing,
+            frames: 0,
+            pieces: 0,
+            garbage_cleared: 0,
+            garbage: GarbageGenerator::new(!seed, messiness),
+            garbage_left: mode.garbage_goal().unwrap_or(0),
+            garbage_timer: 0,
+            last_rotated: false,
+            scripted: false,
+            grade: if mode == GameMode::Master { Some(Grade::new()) } else { None },
+            finesse: Finesse::new(),
+            perfect_clears: 0,
+            pc_streak: 0,
+            best_pc_streak: 0,
+            pc_attempts: 0,
+            reveal_timer: 0,
+            gravity_progress: 0,
+            are_timer: 0,
+            lock_timer: 0,
+            lock_resets: 0,
+            randomizer,
+        };
+        tetris.refill_garbage();
+        if mode == GameMode::PerfectClear {
+            tetris.next_pc_attempt();
         }
+        tetris
     }
 
-    pub fn create_new_tetrimino(&self) -> Tetrimino {
-        static mut PREV: u8 = 7;
-        let mut rand_num = rand::random::<u8>() % 7;
+    /// A game on a prepared board that deals `pieces` in order and
+    /// nothing after them.
+    pub fn with_setup(mode: GameMode, variants: Variants, board: Vec<Vec<u8>>, pieces: &[PieceKind]) -> Tetris {
+        let mut tetris = Tetris::new(mode, variants, board[0].len(), board.len(), 0);
+        tetris.game_map = Board::from_rows(board);
+        tetris.next_pieces = pieces.iter().cloned().collect();
+        tetris.scripted = true;
+        tetris
+    }
 
-        if unsafe { PREV } == rand_num {
-            rand_num = rand::random::<u8>() % 7;
+    /// The whole game as named lists of values, which `from_fields` turns
+    /// back into the same game.
+    pub fn to_fields(&self) -> Vec<(&'static str, Vec<String>)> {
+        fn one<T: ToString>(value: T) -> Vec<String> {
+            vec![value.to_string()]
         }
+        let board = self.game_map.lines()
+            .map(|row| row.iter().map(|case| case.to_string()).collect())
+            .collect();
+        let set = self.variants.piece_kinds();
+        let current = match self.current_tetrimino {
+            Some(ref tetrimino) => vec![
+                piece_code(tetrimino.kind, set).to_string(),
+                tetrimino.rotation.to_string(),
+                tetrimino.x.to_string(),
+                tetrimino.y.to_string(),
+            ],
+            None => Vec::new(),
+        };
+        let piece_set = match self.variants.piece_set {
+            Some(piece_set) => one(&piece_set.source),
+            None => Vec::new(),
+        };
+
+        vec![
+            ("mode", one(self.mode.name())),
+            ("variants", self.variants.names().iter().map(|name| name.to_string()).collect()),
+            ("piece-set", piece_set),
+            ("size", vec![self.width.to_string(), self.height.to_string()]),
+            ("state", one(self.state.name())),
+            ("scoring", one(self.scoring.name())),
+            ("level", one(self.current_level)),
+            ("score", one(self.score)),
+            ("lines", one(self.number_of_lines)),
+            ("frames", one(self.frames)),
+            ("pieces", one(self.pieces)),
+            ("lock-delay", one(self.lock_delay)),
+            ("board", board),
+            ("current", current),
+            ("next", pieces_to_field(self.next_pieces.iter(), set)),
+            ("hold", pieces_to_field(self.hold.iter(), set)),
+            ("hold-used", one(self.hold_used)),
+            ("randomizer", self.randomizer.to_fields()),
+            ("garbage", self.garbage.to_fields()),
+            ("garbage-cleared", one(self.garbage_cleared)),
+            ("garbage-left", one(self.garbage_left)),
+            ("garbage-timer", one(self.garbage_timer)),
+            ("last-rotated", one(self.last_rotated)),
+            ("scripted", one(self.scripted)),
+            ("grade", self.grade.as_ref().map_or(Vec::new(), Grade::to_fields)),
+            ("finesse", self.finesse.to_fields()),
+            ("perfect-clears", one(self.perfect_clears)),
+            ("pc-streak", one(self.pc_streak)),
+            ("best-pc-streak", one(self.best_pc_streak)),
+            ("pc-attempts", one(self.pc_attempts)),
+            ("reveal-timer", one(self.reveal_timer)),
+            ("gravity-progress", one(self.gravity_progress)),
+            ("are-timer", one(self.are_timer)),
+            ("lock-timer", one(self.lock_timer)),
+            ("lock-resets", one(self.lock_resets)),
+        ]
+    }
+
+    /// Rebuilds a game from `to_fields`. The error names the first field
+    /// that is missing or doesn't make sense.
+    pub fn from_fields(fields: &Fields) -> Result<Tetris, String> {
+        let invalid = |key: &str| format!("invalid {}", key);
+        let (width, height) = match *values(fields, "size")? {
+            [width, height] => match (width.parse(), height.parse()) {
+                (Ok(width), Ok(height)) if (MIN_WIDTH..=MAX_WIDTH).contains(&width)
+                    && (MIN_HEIGHT..=MAX_HEIGHT).contains(&height) => (width, height),
+                _ => return Err(invalid("size")),
+            },
+            _ => return Err(invalid("size")),
+        };
+
+        let board = values(fields, "board")?.iter()
+            .map(|row| row.chars().map(|case| case.to_digit(10).map(|case| case as u8)).collect::<Option<Vec<_>>>())
+            .collect::<Option<Vec<_>>>()
+            .filter(|board| {
+                board.len() == height
+                    && board.iter().all(|row| row.len() == width && row.iter().all(|&case| case <= GARBAGE))
+            })
+            .ok_or_else(|| invalid("board"))?;
 
-        unsafe {PREV = rand_num; }
+        let mut variants = Variants::from_names(values(fields, "variants")?.iter().cloned()).ok_or_else(|| invalid("variants"))?;
+        variants.piece_set = match *values(fields, "piece-set")? {
+            [] => None,
+            // A path may have had spaces in it.
+            ref source => Some(PieceSet::load(&source.join(" ")).map_err(|e| format!("piece set {}: {}", source.join(" "), e))?),
+        };
+        let set = variants.piece_kinds();
+        let garbage_columns = width / variants.scale();
 
-        match rand_num {
-            0 => TetriminoI::new(),
-            1 => TetriminoL::new(),
-            2 => TetriminoJ::new(),
-            3 => TetriminoO::new(),
-            4 => TetriminoS::new(),
-            5 => TetriminoZ::new(),
-            6 => TetriminoT::new(),
-            _ => unreachable!(),
+        let mut tetris = Tetris {
+            game_map: Board::from_rows(board),
+            current_level: field(fields, "level")?,
+            score: field(fields, "score")?,
+            number_of_lines: field(fields, "lines")?,
+            current_tetrimino: None,
+            width,
+            height,
+            next_pieces: piece_field(fields, "next", set)?.into_iter().collect(),
+            hold: piece_field(fields, "hold", set)?.first().cloned(),
+            hold_used: field(fields, "hold-used")?,
+            scoring: ScoringProfile::from_name(&field::<String>(fields, "scoring")?).ok_or_else(|| invalid("scoring"))?,
+            lock_delay: field(fields, "lock-delay")?,
+            mode: GameMode::parse(&field::<String>(fields, "mode")?).ok_or_else(|| invalid("mode"))?,
+            variants,
+            state: GameState::from_name(&field::<String>(fields, "state")?).ok_or_else(|| invalid("state"))?,
+            frames: field(fields, "frames")?,
+            pieces: field(fields, "pieces")?,
+            garbage_cleared: field(fields, "garbage-cleared")?,
+            garbage: GarbageGenerator::from_fields(values(fields, "garbage")?, garbage_columns).ok_or_else(|| invalid("garbage"))?,
+            garbage_left: field(fields, "garbage-left")?,
+            garbage_timer: field(fields, "garbage-timer")?,
+            last_rotated: field(fields, "last-rotated")?,
+            scripted: field(fields, "scripted")?,
+            grade: match *values(fields, "grade")? {
+                [] => None,
+                ref grade => Some(Grade::from_fields(grade).ok_or_else(|| invalid("grade"))?),
+            },
+            finesse: Finesse::from_fields(values(fields, "finesse")?).ok_or_else(|| invalid("finesse"))?,
+            perfect_clears: field(fields, "perfect-clears")?,
+            pc_streak: field(fields, "pc-streak")?,
+            best_pc_streak: field(fields, "best-pc-streak")?,
+            pc_attempts: field(fields, "pc-attempts")?,
+            reveal_timer: field(fields, "reveal-timer")?,
+            gravity_progress: field(fields, "gravity-progress")?,
+            are_timer: field(fields, "are-timer")?,
+            lock_timer: field(fields, "lock-timer")?,
+            lock_resets: field(fields, "lock-resets")?,
+            randomizer: Randomizer::from_fields(values(fields, "randomizer")?).ok_or_else(|| invalid("randomizer"))?,
+        };
+        if tetris.current_level == 0 {
+            return Err(invalid("level"));
         }
+
+        tetris.current_tetrimino = match *values(fields, "current")? {
+            [] => None,
+            [piece, state, x, y] => {
+                let kind = match piece.chars().collect::<Vec<_>>()[..] {
+                    [code] => piece_from_code(code, set),
+                    _ => None,
+                };
+                let tetrimino = match (kind, state.parse::<u8>(), x.parse(), y.parse()) {
+                    (Some(kind), Ok(state), Ok(x), Ok(y)) => {
+                        let mut tetrimino = tetris.spawn_tetrimino(kind);
+                        tetrimino.rotation = state;
+                        tetrimino.x = x;
+                        tetrimino.y = y;
+                        Some(tetrimino)
+                    },
+                    _ => None,
+                };
+                let fits = |tetrimino: &Tetrimino| {
+                    (tetrimino.rotation as usize) < tetrimino.rotations()
+                        && tetrimino.test_current_position(&tetris.game_map)
+                };
+                Some(tetrimino.filter(fits).ok_or_else(|| invalid("current"))?)
+            },
+            _ => return Err(invalid("current")),
+        };
+        Ok(tetris)
     }
 
-    fn check_lines(&mut self) {
-        let mut y = 0;
-        let mut score_add = 0;
+    /// Takes the next piece from the queue, or `None` once a scripted
+    /// queue is used up.
+    pub fn create_new_tetrimino(&mut self) -> Option<Tetrimino> {
+        let piece = self.next_pieces.pop_front()?;
+        if !self.scripted {
+            self.next_pieces.push_back(self.randomizer.next_piece(self.variants.piece_kinds()));
+        }
+        Some(self.spawn_tetrimino(piece))
+    }
 
-        while y < self.game_map.len() {
-            let mut complete = true;
+    fn spawn_tetrimino(&self, kind: PieceKind) -> Tetrimino {
+        let mut tetrimino = Tetrimino::new(kind);
+        tetrimino.set_scale(self.variants.scale());
+        // Centered, but still inside narrow boards. Big pieces stay on even
+        // columns so they line up with each other.
+        let scale = tetrimino.scale() as isize;
+        let piece_width = tetrimino.cells().iter().map(|&(x, _)| x + 1).max().unwrap_or(0) - tetrimino.x;
+        let x = ((self.width / 2) as isize + tetrimino.kind.def().spawn_offset * scale).min(self.width as isize - piece_width);
+        tetrimino.x = x / scale * scale;
+        tetrimino
+    }
 
-            for &x in &self.game_map[y] {
-                if x == 0 {
-                    complete = false;
-                    break;
-                }
+    fn spawn(&mut self, tetrimino: Tetrimino) {
+        self.gravity_progress = 0;
+        self.last_rotated = false;
+        self.finesse.start_piece();
+        self.are_timer = 0;
+        self.lock_timer = 0;
+        self.lock_resets = 0;
+
+        if tetrimino.test_current_position(&self.game_map) {
+            self.current_tetrimino = Some(tetrimino);
+        } else {
+            self.state = GameState::ToppedOut;
+        }
+    }
+
+    /// Cells per frame, in 1/`G`.
+    pub fn gravity(&self) -> u32 {
+        if let Some(gravity) = self.mode.gravity() {
+            return gravity;
+        }
+        let level = (self.current_level as usize).min(LEVEL_TIMES.len());
+        G.div_ceil(ms_to_frames(LEVEL_TIMES[level - 1]).max(1))
+    }
+
+    fn lock_frames(&self) -> u32 {
+        self.mode.lock_delay(self.number_of_lines).unwrap_or(self.lock_delay)
+    }
+
+    // Moves the piece down by however many whole cells the gravity has
+    // built up, so 20G reaches the floor within the frame.
+    fn fall(&mut self) {
+        self.gravity_progress += self.gravity();
+        while self.gravity_progress >= G {
+            self.gravity_progress -= G;
+            if !self.move_down() {
+                self.gravity_progress = 0;
+                break;
+            }
+            // Dropping a row gives a fresh lock delay.
+            self.lock_timer = 0;
+        }
+    }
+
+    /// Advances the game by one frame: spawns the next piece, applies
+    /// gravity and locks the piece once the lock delay has run out.
+    pub fn step(&mut self) {
+        if self.state != GameState::Playing {
+            return;
+        }
+        self.frames += 1;
+        self.reveal_timer = self.reveal_timer.saturating_sub(1);
+        self.update_piece();
+        if let Some(ref mut grade) = self.grade {
+            grade.tick();
+        }
+
+        if let Some(interval) = self.mode.garbage_interval(self.frames) {
+            self.garbage_timer += 1;
+            if self.garbage_timer >= interval && self.state == GameState::Playing {
+                self.garbage_timer = 0;
+                self.add_random_garbage();
+            }
+        }
+
+        if let Some(limit) = self.mode.time_limit() {
+            if self.frames >= limit && self.state == GameState::Playing {
+                self.state = GameState::Finished;
+            }
+        }
+    }
+
+    fn update_piece(&mut self) {
+        if self.current_tetrimino.is_none() {
+            if self.are_timer < self.mode.are(self.number_of_lines) {
+                self.are_timer += 1;
+                return;
+            }
+            let next = match self.create_new_tetrimino() {
+                Some(tetrimino) => tetrimino,
+                // What's left in hold can still be played.
+                None => match self.hold.take() {
+                    Some(piece) => self.spawn_tetrimino(piece),
+                    None => {
+                        self.state = GameState::OutOfPieces;
+                        return;
+                    },
+                },
+            };
+            self.spawn(next);
+            // Below 20G a new piece waits a frame before it starts falling.
+            if self.gravity() < 20 * G {
+                return;
+            }
+        }
+
+        self.fall();
+
+        if self.is_grounded() {
+            self.lock_timer += 1;
+            if self.lock_timer > self.lock_frames() {
+                self.make_permanent();
+            }
+        } else {
+            self.lock_timer = 0;
+        }
+    }
+
+    fn is_grounded(&self) -> bool {
+        match self.current_tetrimino {
+            Some(ref tetrimino) => !tetrimino.test_position(
+                &self.game_map,
+                tetrimino.rotation as usize,
+                tetrimino.x,
+                tetrimino.y + 1
+            ),
+            None => false,
+        }
+    }
+
+    fn move_down(&mut self) -> bool {
+        let Some(ref mut tetrimino) = self.current_tetrimino else {
+            return false;
+        };
+        let (x, y) = (tetrimino.x, tetrimino.y + 1);
+        let moved = tetrimino.change_position(&self.game_map, x, y);
+        if moved {
+            self.last_rotated = false;
+        }
+        moved
+    }
+
+    fn reset_lock_delay(&mut self) {
+        if self.lock_resets < self.mode.lock_resets() && self.is_grounded() {
+            self.lock_resets += 1;
+            self.lock_timer = 0;
+        }
+    }
+
+    pub fn move_horizontal(&mut self, shift_x: isize) -> bool {
+        let Some(ref mut tetrimino) = self.current_tetrimino else {
+            return false;
+        };
+        let (x, y) = (tetrimino.x + shift_x * tetrimino.scale() as isize, tetrimino.y);
+        let moved = tetrimino.change_position(&self.game_map, x, y);
+        if moved {
+            self.last_rotated = false;
+            self.reset_lock_delay();
+        }
+        moved
+    }
+
+    pub fn rotate(&mut self, clockwise: bool) -> bool {
+        let floor_kicks = self.mode.floor_kicks();
+        let Some(ref mut tetrimino) = self.current_tetrimino else {
+            return false;
+        };
+        let mut rotated = if clockwise {
+            tetrimino.rotate(&self.game_map)
+        } else {
+            tetrimino.rotate_counterclockwise(&self.game_map)
+        };
+        if !rotated && floor_kicks {
+            rotated = tetrimino.floor_kick(&self.game_map, clockwise);
+        }
+        if rotated {
+            self.last_rotated = true;
+            self.reset_lock_delay();
+        }
+        rotated
+    }
+
+    pub fn soft_drop(&mut self) -> bool {
+        let moved = self.move_down();
+        if moved {
+            self.gravity_progress = 0;
+            self.finesse.soft_drop();
+            if self.scoring == ScoringProfile::Guideline {
+                self.update_score(1);
             }
+        }
+        moved
+    }
 
-            if complete == true {
-                score_add += self.current_level;
-                self.game_map.remove(y);
-                y -= 1;
-                // increase the number of self.lines
+    pub fn hard_drop(&mut self) {
+        let mut cells = 0;
+        while self.move_down() {
+            cells += 1;
+        }
+        if self.current_tetrimino.is_some() {
+            if self.scoring == ScoringProfile::Guideline {
+                self.update_score(cells * 2);
             }
+            self.make_permanent();
+        }
+    }
+
+    /// Swaps the falling piece with the held one, once per piece.
+    pub fn hold(&mut self) {
+        if self.hold_used {
+            return;
+        }
+        let Some(tetrimino) = self.current_tetrimino.take() else {
+            return;
+        };
 
+        let next = match self.hold {
+            Some(piece) => self.spawn_tetrimino(piece),
+            None => match self.create_new_tetrimino() {
+                Some(next) => next,
+                None => {
+                    self.current_tetrimino = Some(tetrimino);
+                    return;
+                },
+            },
+        };
+        self.hold = Some(tetrimino.kind);
+        self.hold_used = true;
+        self.spawn(next);
+    }
+
+    /// The row the falling piece would land on.
+    pub fn ghost_y(&self) -> Option<usize> {
+        let tetrimino = self.current_tetrimino.as_ref()?;
+        let mut y = tetrimino.y;
+        while tetrimino.test_position(&self.game_map, tetrimino.rotation as usize, tetrimino.x, y + 1) {
             y += 1;
         }
+        Some(y)
+    }
 
-        if self.game_map.len() == 0 {
-            // A "Tetris"!
-            score_add += 1000;
+    /// Adds `rows` garbage rows under the stack, each with a hole at
+    /// `hole_column`, wrapped to the board's width, and pushes everything
+    /// up. Blocks pushed out of the top of the board end the game, and so
+    /// does a falling piece that can't be moved up out of the way.
+    pub fn add_garbage(&mut self, rows: usize, hole_column: usize) {
+        for _ in 0..rows {
+            let mut row = vec![GARBAGE; self.width];
+            row[hole_column % self.width] = 0;
+            if self.game_map.push_row(&row) {
+                self.state = GameState::ToppedOut;
+            }
         }
-        
-        self.update_score(score_add);
 
-        while self.game_map.len() < 16 {
+        let game_map = &self.game_map;
+        if let Some(ref mut tetrimino) = self.current_tetrimino {
+            let state = tetrimino.rotation as usize;
+            let lifted = (0..=rows.min(tetrimino.y))
+                .map(|up| tetrimino.y - up)
+                .find(|&y| tetrimino.test_position(game_map, state, tetrimino.x, y));
+            match lifted {
+                Some(y) => tetrimino.y = y,
+                None => self.state = GameState::ToppedOut,
+            }
+        }
+    }
+
+    // Adds a garbage row with its hole where the garbage generator says.
+    // In big mode the hole is as wide as a piece cell.
+    fn add_random_garbage(&mut self) {
+        let scale = self.variants.scale();
+        let hole = self.garbage.next_hole(self.width / scale) * scale;
+        self.add_garbage(1, hole);
+        for column in hole + 1..hole + scale {
+            self.game_map.set(column, self.height - 1, 0);
+        }
+    }
+
+    // Tops the garbage back up to `DIG_VISIBLE_ROWS` rows, leaving room at
+    // the top to spawn, until the mode's garbage runs out.
+    fn refill_garbage(&mut self) {
+        let visible = DIG_VISIBLE_ROWS.min(self.height.saturating_sub(4));
+        let mut rows = self.game_map.lines().filter(|row| row.contains(&GARBAGE)).count();
+        while rows < visible && self.garbage_left > 0 {
+            self.add_random_garbage();
+            self.garbage_left -= 1;
+            rows += 1;
+        }
+    }
+
+    // A T that was rotated into place with three of the four cells
+    // diagonal to its center blocked.
+    fn is_t_spin(&self, tetrimino: &Tetrimino) -> bool {
+        if tetrimino.kind != PieceKind::T || !self.last_rotated {
+            return false;
+        }
+        let cells = tetrimino.cells();
+        let Some(&(x, y)) = cells.iter().find(|&&(x, y)| {
+            [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)].iter().filter(|cell| cells.contains(cell)).count() == 3
+        }) else {
+            return false;
+        };
+        let blocked = [(x - 1, y - 1), (x + 1, y - 1), (x - 1, y + 1), (x + 1, y + 1)].iter()
+            .filter(|&&(x, y)| {
+                x < 0 || y < 0 || x >= self.width as isize || y >= self.height as isize
+                    || self.game_map[y as usize][x as usize] != 0
+            })
+            .count();
+        blocked >= 3
+    }
+
+    // Removes full rows and scores them. Returns how many rows were cleared
+    // and whether that left the whole board empty.
+    fn check_lines(&mut self) -> (u32, bool) {
+        for y in 0..self.height {
+            if self.game_map.is_row_full(y) && self.game_map[y].contains(&GARBAGE) {
+                self.garbage_cleared += 1;
+            }
+        }
+        let lines = self.game_map.clear_lines();
+        for _ in 0..lines {
             self.increase_line();
-            self.game_map.insert(0, vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        }
+        let perfect_clear = lines > 0 && self.game_map.is_empty();
+        if lines > 0 && self.variants.invisible {
+            self.reveal_timer = REVEAL_FRAMES;
+        }
+
+        let mut score_add = match self.scoring {
+            ScoringProfile::Classic => lines * self.current_level,
+            ScoringProfile::Guideline => GUIDELINE_LINE_SCORES[lines.min(4) as usize] * self.current_level,
+        };
+        if perfect_clear {
+            self.perfect_clears += 1;
+            score_add += match self.scoring {
+                ScoringProfile::Classic => CLASSIC_PERFECT_CLEAR_SCORE,
+                ScoringProfile::Guideline => {
+                    GUIDELINE_PERFECT_CLEAR_SCORES[lines.min(4) as usize] * self.current_level
+                },
+            };
+        }
+
+        self.update_score(score_add);
+        if let Some(ref mut grade) = self.grade {
+            grade.award(lines, mode::master_section(self.number_of_lines));
+        }
+        (lines, perfect_clear)
+    }
+
+    // Sets up the next opener for perfect clear training, with the piece
+    // it leaves over in hold. Boards of other widths start empty.
+    fn next_pc_attempt(&mut self) {
+        if self.width == puzzle::PC_OPENER_WIDTH && self.height >= PC_TRAINING_ROWS {
+            let opener = puzzle::pc_opener(self.pc_attempts);
+            self.game_map = Board::from_rows(opener.board(self.height));
+            self.hold = Some(opener.hold);
+        } else {
+            self.game_map = Board::new(self.width, self.height);
+        }
+        self.pc_attempts += 1;
+    }
+
+    // Tracks the streak after a piece locks in perfect clear training.
+    fn update_pc_training(&mut self, perfect_clear: bool) {
+        let too_high = self.game_map.stack_height() > PC_TRAINING_ROWS;
+        if perfect_clear {
+            self.pc_streak += 1;
+            self.best_pc_streak = self.best_pc_streak.max(self.pc_streak);
+            self.next_pc_attempt();
+        } else if too_high {
+            self.pc_streak = 0;
+            self.next_pc_attempt();
         }
     }
 
     pub fn make_permanent(&mut self) {
-        let Some(ref mut tetrimino) = self.current_tetrimino else {
+        let Some(ref tetrimino) = self.current_tetrimino else {
             return;
         };
+        let t_spin = self.is_t_spin(tetrimino);
+        let minimum = finesse::min_inputs(self.spawn_tetrimino(tetrimino.kind), self.width, tetrimino);
+        self.finesse.judge(minimum);
 
-        let mut shift_y = 0;
-        while shift_y < tetrimino.states[tetrimino.current_state as usize].len()
-            && tetrimino.y + shift_y < self.game_map.len()
-        {
-            let mut shift_x = 0;
-            while shift_x < tetrimino.states[tetrimino.current_state as usize][shift_y].len()
-                && (tetrimino.x + shift_x as isize) < self.game_map[tetrimino.y + shift_y].len() as isize
-            {
-                if tetrimino.states[tetrimino.current_state as usize][shift_y][shift_x] != 0 {
-                    let x = tetrimino.x + shift_x as isize;
-                    let y = tetrimino.y + shift_y;
-                    self.game_map[y][x as usize] = tetrimino.states[tetrimino.current_state as usize][shift_y][shift_x];
-                }
-                shift_x += 1;
-            }
-            shift_y += 1;
+        let piece = tetrimino.kind.color();
+        for (x, y) in tetrimino.cells() {
+            self.game_map.set(x as usize, y as usize, piece);
         }
+        self.pieces += 1;
 
-        self.update_score(self.current_level);
-        self.check_lines();
+        if self.scoring == ScoringProfile::Classic {
+            self.update_score(self.current_level);
+        }
+        let (lines, perfect_clear) = self.check_lines();
         self.current_tetrimino = None;
+        self.hold_used = false;
+
+        if self.mode == GameMode::PerfectClear {
+            self.update_pc_training(perfect_clear);
+        }
+        let solved = match self.mode.puzzle_goal() {
+            Some(PuzzleGoal::PerfectClear) => perfect_clear,
+            Some(PuzzleGoal::TSpinDouble) => t_spin && lines == 2,
+            _ => false,
+        };
+        if solved {
+            self.state = GameState::Finished;
+        }
+
+        if let Some(goal) = self.mode.line_goal() {
+            if self.number_of_lines >= goal {
+                self.state = GameState::Finished;
+            }
+        }
+        if let Some(goal) = self.mode.garbage_goal() {
+            if self.garbage_cleared >= goal {
+                self.state = GameState::Finished;
+            } else {
+                self.refill_garbage();
+            }
+        }
     }
 
     fn update_score(&mut self, to_add: u32) {
@@ -131,9 +934,455 @@ impl Tetris {
     fn increase_line(&mut self) {
         self.number_of_lines += 1;
 
-        if self.number_of_lines >= LEVEL_LINES[self.current_level as usize - 1] {
+        if self.number_of_lines >= level_end(self.current_level) {
             self.increase_level();
         }
     }
 
+    /// The leaderboard this game's result goes to.
+    pub fn leaderboard(&self) -> String {
+        self.mode.leaderboard() + &self.variants.suffix()
+    }
+
+    /// FNV-1a over every cell of the board, the same on every platform,
+    /// to compare boards by.
+    pub fn board_hash(&self) -> u64 {
+        self.game_map.lines().flatten().fold(0xcbf2_9ce4_8422_2325, |hash, &case| {
+            (hash ^ case as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+
+    /// Whether the locked blocks should be drawn. Invisible games only
+    /// show them for a moment after a line clear and once they are over.
+    pub fn board_visible(&self) -> bool {
+        !self.variants.invisible || self.reveal_timer > 0 || self.state != GameState::Playing
+    }
+
+    /// The grade in modes that have one. A finished game can be awarded
+    /// the grand master grade.
+    pub fn grade_name(&self) -> Option<&'static str> {
+        self.grade.as_ref().map(|grade| grade.name(self.finished_in()))
+    }
+
+    /// The grade as `Grade::rank` orders it, in modes that have one.
+    pub fn grade_rank(&self) -> Option<u32> {
+        self.grade.as_ref().map(|grade| grade.rank(self.finished_in()))
+    }
+
+    fn finished_in(&self) -> Option<u32> {
+        if self.state == GameState::Finished { Some(self.frames) } else { None }
+    }
+
+    /// Drops the line goal of a finished game and lets it go on until the
+    /// stack tops out.
+    pub fn continue_endless(&mut self) {
+        if self.state == GameState::Finished && self.mode.line_goal().is_some() {
+            self.mode = GameMode::Marathon { lines: None };
+            self.state = GameState::Playing;
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pieceset::PieceSet;
+
+    fn game(width: usize, height: usize) -> Tetris {
+        Tetris::new(GameMode::Marathon { lines: None }, Variants::default(), width, height, 1)
+    }
+
+    // Fills row `y` except the columns in `holes`.
+    fn fill_row(tetris: &mut Tetris, y: usize, holes: &[usize], case: u8) {
+        for x in 0..tetris.width {
+            if !holes.contains(&x) {
+                tetris.game_map.set(x, y, case);
+            }
+        }
+    }
+
+    // Locks a piece at `x`, `y` as if it had landed there.
+    fn lock(tetris: &mut Tetris, kind: PieceKind, rotation: u8, x: isize, y: usize) {
+        let mut tetrimino = tetris.spawn_tetrimino(kind);
+        tetrimino.rotation = rotation;
+        tetrimino.x = x;
+        tetrimino.y = y;
+        assert!(tetrimino.test_current_position(&tetris.game_map));
+        tetris.current_tetrimino = Some(tetrimino);
+        tetris.make_permanent();
+    }
+
+    fn filled_cells(tetris: &Tetris) -> usize {
+        tetris.game_map.lines().flatten().filter(|&&case| case != 0).count()
+    }
+
+    #[test]
+    fn make_permanent_leaves_the_piece_on_the_board() {
+        let mut tetris = game(10, 16);
+        tetris.hold_used = true;
+        lock(&mut tetris, PieceKind::T, 0, 3, 14);
+
+        let mut expected = vec![vec![0; 10]; 16];
+        expected[14][3..6].copy_from_slice(&[7, 7, 7]);
+        expected[15][4] = 7;
+        assert_eq!(tetris.game_map.to_rows(), expected);
+        assert_eq!(tetris.current_tetrimino, None);
+        assert_eq!(tetris.pieces, 1);
+        assert_eq!(tetris.number_of_lines, 0);
+        assert!(!tetris.hold_used);
+        // Classic scoring gives the level for every locked piece.
+        assert_eq!(tetris.score, 1);
+    }
+
+    #[test]
+    fn make_permanent_without_a_piece_does_nothing() {
+        let mut tetris = game(10, 16);
+        tetris.current_tetrimino = None;
+        let before = tetris.clone();
+        tetris.make_permanent();
+        assert_eq!(tetris, before);
+    }
+
+    #[test]
+    fn check_lines_clears_one_to_four_lines() {
+        for lines in 1..=4 {
+            for &scoring in ScoringProfile::ALL.iter() {
+                let mut tetris = game(10, 16);
+                tetris.scoring = scoring;
+                for y in 16 - lines..16 {
+                    fill_row(&mut tetris, y, &[0], 2);
+                }
+                // Keeps the board from being emptied, which scores more.
+                tetris.game_map.set(9, 2, 3);
+                lock(&mut tetris, PieceKind::I, 1, -1, 12);
+
+                assert_eq!(tetris.number_of_lines, lines as u32);
+                let mut expected = vec![vec![0; 10]; 16];
+                expected[2 + lines][9] = 3;
+                for row in expected.iter_mut().skip(12 + lines) {
+                    row[0] = 1;
+                }
+                assert_eq!(tetris.game_map.to_rows(), expected, "{} lines", lines);
+                let score = match scoring {
+                    ScoringProfile::Classic => 1 + lines as u32,
+                    ScoringProfile::Guideline => GUIDELINE_LINE_SCORES[lines],
+                };
+                assert_eq!(tetris.score, score, "{} lines, {} scoring", lines, scoring.name());
+            }
+        }
+    }
+
+    #[test]
+    fn check_lines_clears_rows_that_are_not_next_to_each_other() {
+        let mut tetris = game(10, 16);
+        fill_row(&mut tetris, 13, &[1], 2);
+        fill_row(&mut tetris, 14, &[1, 2], 3);
+        fill_row(&mut tetris, 15, &[1], 2);
+        let kept = tetris.game_map[14].to_vec();
+        lock(&mut tetris, PieceKind::I, 1, 0, 12);
+
+        assert_eq!(tetris.number_of_lines, 2);
+        let mut expected = vec![vec![0; 10]; 16];
+        expected[14][1] = 1;
+        expected[15] = kept;
+        expected[15][1] = 1;
+        assert_eq!(tetris.game_map.to_rows(), expected);
+    }
+
+    #[test]
+    fn check_lines_scores_emptying_the_board() {
+        let mut tetris = game(10, 16);
+        for y in 12..16 {
+            fill_row(&mut tetris, y, &[9], 2);
+        }
+        lock(&mut tetris, PieceKind::I, 1, 8, 12);
+        assert!(tetris.game_map.is_empty());
+        assert_eq!(tetris.perfect_clears, 1);
+        assert_eq!(tetris.score, 1 + 4 + CLASSIC_PERFECT_CLEAR_SCORE);
+    }
+
+    #[test]
+    fn check_lines_counts_cleared_garbage() {
+        let mut tetris = game(10, 16);
+        fill_row(&mut tetris, 14, &[0], GARBAGE);
+        fill_row(&mut tetris, 15, &[0], 2);
+        lock(&mut tetris, PieceKind::I, 1, -1, 12);
+        assert_eq!(tetris.number_of_lines, 2);
+        assert_eq!(tetris.garbage_cleared, 1);
+    }
+
+    #[test]
+    fn level_ends_follow_the_table_then_every_twenty_lines() {
+        assert_eq!(level_end(1), 20);
+        assert_eq!(level_end(10), 200);
+        assert_eq!(level_end(11), 220);
+        assert_eq!(level_end(12), 240);
+    }
+
+    #[test]
+    fn levels_go_up_exactly_at_their_line_counts() {
+        let mut tetris = game(10, 16);
+        let mut level_ups = Vec::new();
+        for _ in 0..240 {
+            let level = tetris.current_level;
+            tetris.increase_line();
+            if tetris.current_level != level {
+                assert_eq!(tetris.current_level, level + 1);
+                level_ups.push(tetris.number_of_lines);
+            }
+        }
+        assert_eq!(level_ups, vec![20, 40, 60, 80, 100, 120, 140, 160, 180, 200, 220, 240]);
+    }
+
+    #[test]
+    fn a_higher_starting_level_waits_for_its_own_line_count() {
+        let mut tetris = game(10, 16);
+        tetris.current_level = 5;
+        for _ in 0..99 {
+            tetris.increase_line();
+        }
+        assert_eq!(tetris.current_level, 5);
+        tetris.increase_line();
+        assert_eq!(tetris.current_level, 6);
+    }
+
+    #[test]
+    fn line_clears_raise_the_level() {
+        let mut tetris = game(10, 16);
+        tetris.number_of_lines = 19;
+        fill_row(&mut tetris, 15, &[0], 2);
+        tetris.game_map.set(9, 2, 3);
+        lock(&mut tetris, PieceKind::I, 1, -1, 12);
+        assert_eq!((tetris.number_of_lines, tetris.current_level), (20, 2));
+    }
+
+    fn check_board(tetris: &Tetris, width: usize, height: usize) {
+        assert_eq!((tetris.width, tetris.height), (width, height));
+        assert_eq!(tetris.game_map.height(), height);
+        assert_eq!(tetris.game_map.lines().count(), height);
+        assert!(tetris.game_map.lines().all(|row| row.len() == width));
+        assert!(tetris.game_map.lines().flatten().all(|&case| case <= GARBAGE));
+        if let Some(ref tetrimino) = tetris.current_tetrimino {
+            for (x, y) in tetrimino.cells() {
+                assert!((0..width as isize).contains(&x) && (0..height as isize).contains(&y));
+                assert_eq!(tetris.game_map[y as usize][x as usize], 0, "piece on a block at {}, {}", x, y);
+            }
+        }
+    }
+
+    // Covered empty cells and the stack height, lower for a tidier board.
+    fn untidiness(tetris: &Tetris) -> usize {
+        let holes = (0..tetris.width)
+            .map(|x| {
+                let column = tetris.game_map.lines().map(|row| row[x]).skip_while(|&case| case == 0);
+                column.filter(|&case| case == 0).count()
+            })
+            .sum::<usize>();
+        holes + tetris.game_map.stack_height()
+    }
+
+    // Turns and a column for the falling piece: mostly the ones that leave
+    // the tidiest board, so lines get cleared, sometimes any at all.
+    fn choose_target(tetris: &Tetris, inputs: &mut Randomizer) -> (u32, isize) {
+        // Left of column 0 too, for pieces with empty columns on the left.
+        let columns = -2..tetris.width as i32;
+        if inputs.random_ratio(1, 4) {
+            return (inputs.random_range(0..4), inputs.random_range(columns) as isize);
+        }
+        let mut best = Vec::new();
+        let mut best_untidiness = usize::MAX;
+        for turns in 0..4 {
+            for column in columns.clone().map(|column| column as isize) {
+                let mut landed = tetris.clone();
+                for _ in 0..turns {
+                    landed.rotate(true);
+                }
+                let Some(mut tetrimino) = landed.current_tetrimino else {
+                    continue;
+                };
+                if !tetrimino.change_position(&landed.game_map, column, tetrimino.y) {
+                    continue;
+                }
+                landed.current_tetrimino = Some(tetrimino);
+                landed.hard_drop();
+                let untidiness = untidiness(&landed);
+                if untidiness < best_untidiness {
+                    best_untidiness = untidiness;
+                    best.clear();
+                }
+                if untidiness == best_untidiness {
+                    best.push((turns, column));
+                }
+            }
+        }
+        if best.is_empty() {
+            (0, 0)
+        } else {
+            best[inputs.random_range(0..best.len())]
+        }
+    }
+
+    // Random play, checking after every input and frame that the board
+    // keeps its size, the falling piece never overlaps a block, a locked
+    // piece adds exactly its cells less the cleared rows, and the score and
+    // line count never go down. Each piece is turned and moved to a chosen
+    // column before it is dropped, now and then with a stray input.
+    fn play_randomly(variants: Variants, width: usize, height: usize, seed: u64) -> u32 {
+        let mut tetris = Tetris::new(GameMode::Marathon { lines: None }, variants, width, height, seed);
+        tetris.lock_delay = ms_to_frames(500);
+        let mut inputs = Randomizer::new(seed ^ 0x5eed);
+        // Turns left and the column to drop the falling piece at.
+        let mut target = None;
+        check_board(&tetris, width, height);
+
+        while tetris.state == GameState::Playing && tetris.frames < 3000 {
+            for input in 0..2 {
+                let (score, lines, pieces) = (tetris.score, tetris.number_of_lines, tetris.pieces);
+                let filled = filled_cells(&tetris);
+                let piece_cells = tetris.current_tetrimino.map_or(0, |tetrimino| tetrimino.cells().len());
+
+                if input == 1 {
+                    tetris.step();
+                } else if let Some(x) = tetris.current_tetrimino.map(|tetrimino| tetrimino.x) {
+                    let (turns, column) = match target {
+                        Some(target) => target,
+                        None => choose_target(&tetris, &mut inputs),
+                    };
+                    target = Some((turns, column));
+                    match inputs.random_range(0..20) {
+                        0 => { tetris.rotate(false); },
+                        1 => { tetris.soft_drop(); },
+                        2 => {
+                            tetris.hold();
+                            target = None;
+                        },
+                        _ if turns > 0 => {
+                            tetris.rotate(true);
+                            target = Some((turns - 1, column));
+                        },
+                        _ if x != column && tetris.move_horizontal((column - x).signum()) => {},
+                        _ => {
+                            tetris.hard_drop();
+                            target = None;
+                        },
+                    }
+                } else {
+                    target = None;
+                }
+
+                check_board(&tetris, width, height);
+                assert!(tetris.score >= score, "score went down");
+                assert!(tetris.number_of_lines >= lines, "lines went down");
+                let cleared = (tetris.number_of_lines - lines) as usize;
+                match tetris.pieces - pieces {
+                    0 => assert_eq!(filled_cells(&tetris), filled),
+                    1 => assert_eq!(filled_cells(&tetris), filled + piece_cells - cleared * width),
+                    locked => panic!("{} pieces locked at once", locked),
+                }
+            }
+        }
+        tetris.number_of_lines
+    }
+
+    #[test]
+    fn random_play_keeps_the_board_consistent() {
+        let lines = (0..12).map(|seed| play_randomly(Variants::default(), 10, 16, seed)).sum::<u32>();
+        assert!(lines > 0, "no lines were cleared");
+    }
+
+    #[test]
+    fn random_big_play_keeps_the_board_consistent() {
+        let variants = Variants { big: true, ..Variants::default() };
+        let lines = (0..6).map(|seed| play_randomly(variants, 10, 20, seed)).sum::<u32>();
+        assert!(lines > 0, "no lines were cleared");
+    }
+
+    #[test]
+    fn random_play_with_piece_sets_keeps_the_board_consistent() {
+        for &(set, width) in [("pentominoes", 10), ("small", 6)].iter() {
+            let variants = Variants { piece_set: Some(PieceSet::load(set).unwrap()), ..Variants::default() };
+            let lines = (0..6).map(|seed| play_randomly(variants, width, 20, seed)).sum::<u32>();
+            assert!(lines > 0, "no lines were cleared with {}", set);
+        }
+    }
+
+    #[test]
+    fn garbage_hole_wraps_around() {
+        let mut tetris = game(10, 20);
+        tetris.current_tetrimino = None;
+        tetris.add_garbage(2, 13);
+        let rows = tetris.game_map.to_rows();
+        for row in &rows[18..] {
+            let holes = (0..10).filter(|&x| row[x] == 0).collect::<Vec<_>>();
+            assert_eq!(holes, [3]);
+        }
+        assert_eq!(filled_cells(&tetris), 18);
+        assert_eq!(tetris.state, GameState::Playing);
+    }
+
+    #[test]
+    fn saves_with_out_of_range_garbage_are_rejected() {
+        let mut tetris = Tetris::new(GameMode::Survival { messiness: 30 }, Variants { big: true, ..Variants::default() }, 10, 20, 3);
+        tetris.add_random_garbage();
+        let saved = tetris.to_fields();
+        let with_garbage = |garbage: &[&str]| {
+            let mut fields = saved.iter()
+                .map(|(key, values)| (*key, values.iter().map(String::as_str).collect()))
+                .collect::<Fields>();
+            let mut values = fields["garbage"][..2].to_vec();
+            values.extend_from_slice(garbage);
+            fields.insert("garbage", values);
+            Tetris::from_fields(&fields)
+        };
+
+        assert_eq!(with_garbage(&["100", "4"]).unwrap().garbage.hole, Some(4));
+        assert_eq!(with_garbage(&["101", "4"]).unwrap_err(), "invalid garbage");
+        assert_eq!(with_garbage(&["30", "5"]).unwrap_err(), "invalid garbage");
+    }
+
+    #[test]
+    fn pc_training_moves_on_to_the_next_opener_after_a_perfect_clear() {
+        let mut tetris = Tetris::new(GameMode::PerfectClear, Variants::default(), 10, 20, 1);
+        assert_eq!(tetris.game_map.to_rows(), puzzle::pc_opener(0).board(20));
+        assert_eq!(tetris.hold, Some(PieceKind::T));
+
+        // The O, L and J of the next bag, then the I on top.
+        for &(x, y, kind) in &[
+            (6, 18, PieceKind::O), (7, 18, PieceKind::O), (6, 19, PieceKind::O), (7, 19, PieceKind::O),
+            (4, 16, PieceKind::L), (5, 16, PieceKind::L), (5, 17, PieceKind::L), (5, 18, PieceKind::L),
+            (6, 17, PieceKind::J), (7, 17, PieceKind::J), (8, 17, PieceKind::J), (8, 18, PieceKind::J),
+        ] {
+            tetris.game_map.set(x, y, kind.color());
+        }
+        lock(&mut tetris, PieceKind::I, 0, 6, 16);
+
+        assert_eq!(tetris.perfect_clears, 1);
+        assert_eq!(tetris.pc_streak, 1);
+        assert_eq!(tetris.game_map.to_rows(), puzzle::pc_opener(1).board(20));
+        assert_eq!(tetris.hold, Some(PieceKind::T));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_keeps_custom_pieces() {
+        let variants = Variants { piece_set: Some(PieceSet::load("pentominoes").unwrap()), ..Variants::default() };
+        let mut tetris = Tetris::new(GameMode::Marathon { lines: None }, variants, 12, 24, 7);
+        for drop in 0..3 {
+            while tetris.current_tetrimino.is_none() {
+                tetris.step();
+            }
+            if drop == 0 {
+                tetris.hold();
+            } else {
+                tetris.hard_drop();
+            }
+        }
+        assert!(tetris.hold.is_some() && tetris.pieces > 0);
+
+        let json = serde_json::to_string(&tetris).unwrap();
+        let loaded: Tetris = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, tetris);
+        assert!(std::ptr::eq(loaded.variants.piece_set.unwrap(), variants.piece_set.unwrap()));
+    }
 }